@@ -0,0 +1,4 @@
+ALTER TABLE issue_delivery_queue
+	ADD COLUMN n_attempts SMALLINT NOT NULL DEFAULT 0,
+	ADD COLUMN next_attempt_at timestamptz NOT NULL DEFAULT now(),
+	ADD COLUMN last_error TEXT NULL;
@@ -0,0 +1,23 @@
+-- We wrap the whole operation in a transaction to make it succeeds or fails atomically.
+BEGIN;
+	ALTER TABLE newsletter_issues
+		ADD COLUMN n_recipients INTEGER NOT NULL DEFAULT 0,
+		ADD COLUMN n_delivered INTEGER NOT NULL DEFAULT 0,
+		ADD COLUMN n_failed INTEGER NOT NULL DEFAULT 0,
+		ADD COLUMN delivery_completed_at timestamptz NULL;
+	-- Backfill what we still know about historical issues: what is left in the queue and
+	-- what has failed. Deliveries that already went out were never recorded.
+	UPDATE newsletter_issues i
+		SET
+			n_failed = (
+				SELECT COUNT(*) FROM issue_delivery_failures f
+				WHERE f.newsletter_issue_id = i.newsletter_issue_id
+			),
+			n_recipients = (
+				SELECT COUNT(*) FROM issue_delivery_failures f
+				WHERE f.newsletter_issue_id = i.newsletter_issue_id
+			) + (
+				SELECT COUNT(*) FROM issue_delivery_queue q
+				WHERE q.newsletter_issue_id = i.newsletter_issue_id
+			);
+COMMIT;
@@ -0,0 +1,2 @@
+-- Issues with a scheduled time are only delivered once it is due, NULL means "send right away"
+ALTER TABLE newsletter_issues ADD COLUMN scheduled_for timestamptz NULL;
@@ -0,0 +1,4 @@
+-- Drafts are issues that have not been published yet: they have no publication time
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+-- Test sends of a draft go to the email address of the admin who asked for them
+ALTER TABLE users ADD COLUMN email TEXT NULL;
@@ -0,0 +1,2 @@
+-- The Markdown source of issues written in Markdown, NULL for issues written in HTML
+ALTER TABLE newsletter_issues ADD COLUMN markdown_content TEXT NULL;
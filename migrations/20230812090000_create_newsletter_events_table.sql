@@ -0,0 +1,12 @@
+CREATE TABLE newsletter_events (
+	newsletter_event_id uuid PRIMARY KEY,
+	newsletter_issue_id uuid NOT NULL
+		REFERENCES newsletter_issues (newsletter_issue_id) ON DELETE CASCADE,
+	subscriber_id uuid NOT NULL
+		REFERENCES subscriptions (id) ON DELETE CASCADE,
+	event_type TEXT NOT NULL CHECK (event_type IN ('open', 'click')),
+	-- The destination of a click, NULL for opens
+	url TEXT NULL,
+	occurred_at timestamptz NOT NULL
+);
+CREATE INDEX newsletter_events_issue_idx ON newsletter_events (newsletter_issue_id, event_type);
@@ -0,0 +1,2 @@
+-- Published issues are listed in the public archive unless an admin takes them out
+ALTER TABLE newsletter_issues ADD COLUMN in_archive BOOLEAN NOT NULL DEFAULT TRUE;
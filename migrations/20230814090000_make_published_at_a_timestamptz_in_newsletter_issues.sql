@@ -0,0 +1,23 @@
+-- `published_at` was filled with `now()` even though the column is TEXT, so every value is
+-- Postgres' own rendering of a timestamptz and casts back to one. Values that do not are kept
+-- published: they fall back to when their delivery completed, or to the time of the migration.
+BEGIN;
+	CREATE FUNCTION pg_temp.try_cast_to_timestamptz(value TEXT) RETURNS timestamptz AS $$
+	BEGIN
+		RETURN value::timestamptz;
+	EXCEPTION WHEN others THEN
+		RETURN NULL;
+	END;
+	$$ LANGUAGE plpgsql;
+	ALTER TABLE newsletter_issues
+		ALTER COLUMN published_at TYPE timestamptz
+		USING CASE
+			WHEN published_at IS NULL THEN NULL
+			ELSE COALESCE(
+				pg_temp.try_cast_to_timestamptz(published_at),
+				delivery_completed_at,
+				now()
+			)
+		END;
+	CREATE INDEX newsletter_issues_published_at_idx ON newsletter_issues (published_at);
+COMMIT;
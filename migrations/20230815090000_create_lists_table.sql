@@ -0,0 +1,13 @@
+-- Named audiences: an issue sent to lists only reaches the confirmed members of those lists
+CREATE TABLE lists (
+	list_id uuid NOT NULL,
+	name TEXT NOT NULL UNIQUE,
+	created_at timestamptz NOT NULL,
+	PRIMARY KEY (list_id)
+);
+CREATE TABLE subscriptions_lists (
+	subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+	list_id uuid NOT NULL REFERENCES lists (list_id) ON DELETE CASCADE,
+	PRIMARY KEY (subscriber_id, list_id)
+);
+CREATE INDEX subscriptions_lists_list_id_idx ON subscriptions_lists (list_id);
@@ -0,0 +1,2 @@
+-- A new address chosen in the preference center only replaces `email` once it is confirmed
+ALTER TABLE subscriptions ADD COLUMN pending_email TEXT NULL;
@@ -0,0 +1,13 @@
+-- Tokens issued before this migration are treated as created when their subscriber signed up
+ALTER TABLE subscription_tokens ADD COLUMN created_at timestamptz NULL;
+UPDATE subscription_tokens
+SET created_at = subscriptions.subscribed_at
+FROM subscriptions
+WHERE subscriptions.id = subscription_tokens.subscriber_id;
+ALTER TABLE subscription_tokens ALTER COLUMN created_at SET DEFAULT now();
+ALTER TABLE subscription_tokens ALTER COLUMN created_at SET NOT NULL;
+
+-- A token is consumed once it confirmed its subscriber
+ALTER TABLE subscription_tokens ADD COLUMN consumed_at timestamptz NULL;
+
+CREATE INDEX subscription_tokens_subscriber_id_idx ON subscription_tokens (subscriber_id);
@@ -0,0 +1,18 @@
+-- Transactional emails are written here in the same transaction as the change that triggers them
+-- and sent by the background worker, so a provider outage only delays them
+CREATE TABLE email_outbox (
+	email_id uuid NOT NULL,
+	recipient TEXT NOT NULL,
+	subject TEXT NOT NULL,
+	html_content TEXT NOT NULL,
+	text_content TEXT NOT NULL,
+	created_at timestamptz NOT NULL DEFAULT now(),
+	n_attempts SMALLINT NOT NULL DEFAULT 0,
+	next_attempt_at timestamptz NOT NULL DEFAULT now(),
+	last_error TEXT NULL,
+	-- Set once the email is given up on, the row is kept for inspection
+	failed_at timestamptz NULL,
+	PRIMARY KEY(email_id)
+);
+CREATE INDEX email_outbox_next_attempt_at_idx ON email_outbox (next_attempt_at)
+	WHERE failed_at IS NULL;
@@ -0,0 +1,22 @@
+-- Append-only history of what every subscriber agreed to, and when, to prove their consent
+CREATE TABLE consent_events (
+	consent_event_id uuid NOT NULL,
+	subscriber_id uuid NOT NULL
+		REFERENCES subscriptions (id),
+	-- `signup`, `confirmation`, `email_change` or `unsubscribe`
+	event_type TEXT NOT NULL,
+	-- The address the event was about, subscribers can change theirs later on
+	email TEXT NOT NULL,
+	occurred_at timestamptz NOT NULL,
+	ip_address TEXT NULL,
+	user_agent TEXT NULL,
+	-- The form a signup was submitted from
+	source TEXT NULL,
+	PRIMARY KEY (consent_event_id)
+);
+CREATE INDEX consent_events_subscriber_id_idx ON consent_events (subscriber_id, occurred_at);
+
+-- Subscribers who signed up before consent was recorded only have their signup date
+INSERT INTO consent_events (consent_event_id, subscriber_id, event_type, email, occurred_at)
+SELECT md5(id::text || 'signup')::uuid, id, 'signup', email, subscribed_at
+FROM subscriptions;
@@ -0,0 +1,2 @@
+-- Hiding or showing an issue changes the public feeds, their Last-Modified date has to move too
+ALTER TABLE newsletter_issues ADD COLUMN archive_updated_at timestamptz NULL;
@@ -241,14 +241,14 @@ mod tests {
 
     #[test]
     fn password_more_then_128_character_should_failed() {
-        let password = Secret::new("password12".repeat(13).into());
+        let password = Secret::new("password12".repeat(13));
         let parsed_passeord = Password::parse(&password);
         assert_err!(parsed_passeord);
     }
 
     #[test]
     fn graphemes_password_() {
-        let password = Secret::new("ぁ😤😠😡🤬🤯😳🥵🥶😱".repeat(12).into());
+        let password = Secret::new("ぁ😤😠😡🤬🤯😳🥵🥶😱".repeat(12));
         let parsed_passeord = Password::parse(&password);
         assert_ok!(parsed_passeord);
     }
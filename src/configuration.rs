@@ -1,10 +1,12 @@
 use crate::domain::SubscriberEmail;
-use crate::email_client::EmailClient;
+use crate::email_client::{EmailSender, FileSinkEmailClient, PostmarkEmailClient, SmtpEmailClient};
+use crate::link_signer::LinkSigner;
 use reqwest;
 use secrecy::{ExposeSecret, Secret};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use sqlx::ConnectOptions;
+use std::sync::Arc;
 
 // List of valid runtime time environments
 pub enum Environment {
@@ -42,6 +44,7 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub worker: WorkerSettings,
     pub redis_uri: Secret<String>,
 }
 
@@ -52,6 +55,10 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    // Only set when a proxy in front of the application overwrites `Forwarded`/`X-Forwarded-For`,
+    // otherwise clients can put any address in them
+    #[serde(default)]
+    pub trust_forwarded_headers: bool,
 }
 
 #[derive(serde::Deserialize, Clone)]
@@ -67,10 +74,56 @@ pub struct DatabaseSettings {
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
+    #[serde(default)]
+    pub backend: EmailBackend,
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    pub smtp: Option<SmtpSettings>,
+    pub file_sink_directory: Option<String>,
+}
+
+// List of supported email delivery backends
+#[derive(serde::Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailBackend {
+    #[default]
+    Postmark,
+    Smtp,
+    File,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    pub require_tls: bool,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct WorkerSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: i16,
+    pub initial_backoff_milliseconds: u64,
+    pub max_backoff_milliseconds: u64,
+    // Deliveries locked and sent together by a single worker iteration
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub batch_size: u16,
+    // Number of worker loops pulling from the delivery queue concurrently
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub pool_size: u16,
+    // How long an idle worker waits before polling an empty queue again
+    pub poll_interval_milliseconds: u64,
+    // How long a worker waits after an unexpected error (e.g. the database is unreachable)
+    pub error_backoff_milliseconds: u64,
+    // Embed a tracking pixel in the HTML body of every issue to record opens
+    pub track_opens: bool,
+    // Route the links of every issue through a redirect that records clicks
+    pub track_clicks: bool,
 }
 
 impl ApplicationSettings {
@@ -80,6 +133,10 @@ impl ApplicationSettings {
             Err(_) => Err(format!("{} is not a valid url.", self.base_url)),
         }
     }
+    pub fn link_signer(&self) -> LinkSigner {
+        let base_url = self.base_url().expect("Invalid application base url.");
+        LinkSigner::new(base_url, self.hmac_secret.clone())
+    }
 }
 
 impl DatabaseSettings {
@@ -107,11 +164,43 @@ impl DatabaseSettings {
 impl EmailClientSettings {
     // This to allow for background worker testing - it slightly different but it allows us to
     // test background proccess effectively
-    pub fn client(self) -> EmailClient {
+    pub fn client(self) -> Arc<dyn EmailSender> {
         let sender_email = self.sender().expect("Failed sender email address");
         let timeout = self.tineout();
-        let url = self.url().unwrap();
-        EmailClient::new(sender_email, url, self.authorization_token, timeout)
+        match self.backend {
+            EmailBackend::Postmark => {
+                let url = self.url().unwrap();
+                Arc::new(PostmarkEmailClient::new(
+                    sender_email,
+                    url,
+                    self.authorization_token,
+                    timeout,
+                ))
+            }
+            EmailBackend::Smtp => {
+                let smtp = self
+                    .smtp
+                    .expect("The smtp email backend requires `email_client.smtp` settings.");
+                let credentials = smtp.username.zip(smtp.password);
+                Arc::new(
+                    SmtpEmailClient::new(
+                        sender_email,
+                        &smtp.host,
+                        smtp.port,
+                        credentials,
+                        smtp.require_tls,
+                        timeout,
+                    )
+                    .expect("Invalid smtp relay."),
+                )
+            }
+            EmailBackend::File => {
+                let directory = self
+                    .file_sink_directory
+                    .expect("The file email backend requires `email_client.file_sink_directory`.");
+                Arc::new(FileSinkEmailClient::new(sender_email, directory))
+            }
+        }
     }
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
@@ -127,6 +216,21 @@ impl EmailClientSettings {
     }
 }
 
+impl WorkerSettings {
+    pub fn initial_backoff(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.initial_backoff_milliseconds)
+    }
+    pub fn max_backoff(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.max_backoff_milliseconds)
+    }
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.poll_interval_milliseconds)
+    }
+    pub fn error_backoff(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.error_backoff_milliseconds)
+    }
+}
+
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     let base_path = std::env::current_dir().expect("Failed to determine current directory.");
     let configuration_directory = base_path.join("configuration");
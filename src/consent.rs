@@ -0,0 +1,149 @@
+use actix_web::http::header::USER_AGENT;
+use actix_web::{web, HttpRequest};
+use chrono::Utc;
+use sqlx::{Postgres, Transaction};
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+const MAX_USER_AGENT_LENGTH: usize = 512;
+const MAX_SOURCE_LENGTH: usize = 100;
+
+#[derive(Debug, Clone, Copy)]
+pub enum ConsentEventType {
+    Signup,
+    Confirmation,
+    EmailChange,
+    Unsubscribe,
+}
+
+impl ConsentEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConsentEventType::Signup => "signup",
+            ConsentEventType::Confirmation => "confirmation",
+            ConsentEventType::EmailChange => "email_change",
+            ConsentEventType::Unsubscribe => "unsubscribe",
+        }
+    }
+}
+
+// Set from `application.trust_forwarded_headers`
+pub struct TrustForwardedHeaders(pub bool);
+
+// Who gave (or withdrew) their consent, as far as the request tells us
+#[derive(Debug)]
+pub struct ConsentContext {
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl ConsentContext {
+    // The client address is taken from `Forwarded`/`X-Forwarded-For` only when the application is
+    // configured to trust them, otherwise it is the address of the peer connecting to us
+    pub fn from_request(request: &HttpRequest) -> Self {
+        let trust_forwarded_headers = request
+            .app_data::<web::Data<TrustForwardedHeaders>>()
+            .is_some_and(|trust| trust.0);
+        let connection_info = request.connection_info();
+        let ip_address = if trust_forwarded_headers {
+            connection_info.realip_remote_addr()
+        } else {
+            connection_info.peer_addr()
+        }
+        .map(|address| match address.parse::<SocketAddr>() {
+            Ok(socket_address) => socket_address.ip().to_string(),
+            Err(_) => address.to_owned(),
+        });
+        let user_agent = request
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|user_agent| user_agent.to_str().ok())
+            .map(|user_agent| truncate(user_agent, MAX_USER_AGENT_LENGTH));
+        Self {
+            ip_address,
+            user_agent,
+        }
+    }
+}
+
+// Recorded in the same transaction as the change it is about, so neither exists without the other
+#[tracing::instrument(name = "Record a consent event", skip(transaction, email, source))]
+pub async fn record_consent_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    event_type: ConsentEventType,
+    email: &str,
+    context: &ConsentContext,
+    source: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO consent_events (
+            consent_event_id,
+            subscriber_id,
+            event_type,
+            email,
+            occurred_at,
+            ip_address,
+            user_agent,
+            source
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        event_type.as_str(),
+        email,
+        Utc::now(),
+        context.ip_address,
+        context.user_agent,
+        source.map(|source| truncate(source, MAX_SOURCE_LENGTH))
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+fn truncate(s: &str, max_length: usize) -> String {
+    s.chars().take(max_length).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::consent::{ConsentContext, TrustForwardedHeaders};
+    use actix_web::test::TestRequest;
+    use actix_web::web;
+
+    #[test]
+    fn the_client_address_and_user_agent_are_taken_from_the_request() {
+        let request = TestRequest::default()
+            .peer_addr("203.0.113.7:51234".parse().unwrap())
+            .insert_header(("User-Agent", "x".repeat(1000)))
+            .to_http_request();
+        let context = ConsentContext::from_request(&request);
+        assert_eq!(context.ip_address.as_deref(), Some("203.0.113.7"));
+        assert_eq!(context.user_agent.unwrap().len(), 512);
+    }
+
+    #[test]
+    fn forwarded_headers_are_ignored_unless_trusted() {
+        let request = TestRequest::default()
+            .peer_addr("10.0.0.1:51234".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "198.51.100.4"))
+            .to_http_request();
+        let context = ConsentContext::from_request(&request);
+        assert_eq!(context.ip_address.as_deref(), Some("10.0.0.1"));
+        assert_eq!(context.user_agent, None);
+    }
+
+    #[test]
+    fn a_forwarded_client_address_is_preferred_behind_a_trusted_proxy() {
+        let request = TestRequest::default()
+            .peer_addr("10.0.0.1:51234".parse().unwrap())
+            .insert_header(("X-Forwarded-For", "198.51.100.4"))
+            .app_data(web::Data::new(TrustForwardedHeaders(true)))
+            .to_http_request();
+        let context = ConsentContext::from_request(&request);
+        assert_eq!(context.ip_address.as_deref(), Some("198.51.100.4"));
+    }
+}
@@ -1,7 +1,9 @@
 mod new_subscriber;
+mod newsletter_template;
 mod subscriber_email;
 mod subscriber_name;
 
 pub use new_subscriber::NewSubscriber;
+pub use newsletter_template::{MergeFields, NewsletterTemplate};
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
@@ -0,0 +1,159 @@
+use crate::utils::escape_html;
+
+const OPENING_DELIMITER: &str = "{{";
+const CLOSING_DELIMITER: &str = "}}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MergeField {
+    Name,
+    Email,
+    UnsubscribeUrl,
+}
+
+impl MergeField {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "name" => Some(Self::Name),
+            "email" => Some(Self::Email),
+            "unsubscribe_url" => Some(Self::UnsubscribeUrl),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Segment<'a> {
+    Text(&'a str),
+    Field(MergeField),
+}
+
+// The per-subscriber values substituted into the placeholders of an issue
+pub struct MergeFields<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+    pub unsubscribe_url: &'a str,
+}
+
+impl MergeFields<'_> {
+    fn value(&self, field: MergeField) -> &str {
+        match field {
+            MergeField::Name => self.name,
+            MergeField::Email => self.email,
+            MergeField::UnsubscribeUrl => self.unsubscribe_url,
+        }
+    }
+}
+
+// A newsletter body with `{{ name }}`, `{{ email }}` and `{{ unsubscribe_url }}` placeholders.
+// Parsing checks every placeholder up front, so rendering for a subscriber cannot fail.
+#[derive(Debug)]
+pub struct NewsletterTemplate<'a>(Vec<Segment<'a>>);
+
+impl<'a> NewsletterTemplate<'a> {
+    pub fn parse(template: &'a str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find(OPENING_DELIMITER) {
+            if start > 0 {
+                segments.push(Segment::Text(&rest[..start]));
+            }
+            let after_opening = &rest[start + OPENING_DELIMITER.len()..];
+            let end = after_opening.find(CLOSING_DELIMITER).ok_or_else(|| {
+                format!(
+                    "A placeholder is missing its closing braces: {}",
+                    snippet(&rest[start..])
+                )
+            })?;
+            let name = after_opening[..end].trim();
+            let field = MergeField::parse(name).ok_or_else(|| {
+                format!(
+                    "{{{{ {} }}}} is not a known merge field. \
+                    Use {{{{ name }}}}, {{{{ email }}}} or {{{{ unsubscribe_url }}}}.",
+                    name
+                )
+            })?;
+            segments.push(Segment::Field(field));
+            rest = &after_opening[end + CLOSING_DELIMITER.len()..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest));
+        }
+        Ok(Self(segments))
+    }
+
+    pub fn render_text(&self, fields: &MergeFields) -> String {
+        self.render(fields, str::to_owned)
+    }
+
+    // Subscriber names are user supplied, they must not be able to inject markup into the issue
+    pub fn render_html(&self, fields: &MergeFields) -> String {
+        self.render(fields, escape_html)
+    }
+
+    fn render(&self, fields: &MergeFields, encode: impl Fn(&str) -> String) -> String {
+        let mut rendered = String::new();
+        for segment in &self.0 {
+            match segment {
+                Segment::Text(text) => rendered.push_str(text),
+                Segment::Field(field) => rendered.push_str(&encode(fields.value(*field))),
+            }
+        }
+        rendered
+    }
+}
+
+// Enough of the broken placeholder for the author to find it in their issue
+fn snippet(s: &str) -> String {
+    s.chars().take(30).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::{MergeFields, NewsletterTemplate};
+    use claims::assert_err;
+
+    fn merge_fields() -> MergeFields<'static> {
+        MergeFields {
+            name: "Ursula <Le Guin>",
+            email: "ursula@example.com",
+            unsubscribe_url: "https://example.com/unsubscribe?token=abc",
+        }
+    }
+
+    #[test]
+    fn a_template_without_placeholders_is_rendered_verbatim() {
+        let template = NewsletterTemplate::parse("Hello there!").unwrap();
+        assert_eq!(template.render_text(&merge_fields()), "Hello there!");
+    }
+
+    #[test]
+    fn placeholders_are_replaced_with_the_subscriber_fields() {
+        let template =
+            NewsletterTemplate::parse("Hi {{ name }} ({{email}}), leave: {{  unsubscribe_url }}")
+                .unwrap();
+        assert_eq!(
+            template.render_text(&merge_fields()),
+            "Hi Ursula <Le Guin> (ursula@example.com), \
+            leave: https://example.com/unsubscribe?token=abc"
+        );
+    }
+
+    #[test]
+    fn fields_are_escaped_when_rendering_html() {
+        let template = NewsletterTemplate::parse("<p>Hi {{ name }}</p>").unwrap();
+        assert_eq!(
+            template.render_html(&merge_fields()),
+            "<p>Hi Ursula &lt;Le Guin&gt;</p>"
+        );
+    }
+
+    #[test]
+    fn unknown_merge_fields_are_rejected() {
+        assert_err!(NewsletterTemplate::parse("Hi {{ first_name }}"));
+    }
+
+    #[test]
+    fn unclosed_placeholders_are_rejected() {
+        assert_err!(NewsletterTemplate::parse("Hi {{ name"));
+    }
+}
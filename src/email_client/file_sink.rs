@@ -0,0 +1,89 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::message::build_message;
+use crate::email_client::{EmailHeader, EmailSender};
+use anyhow::Context;
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+use std::path::PathBuf;
+
+// Writes every email as an `.eml` file in a directory instead of sending it. Meant for local
+// development, where the files can be opened with any mail client.
+pub struct FileSinkEmailClient {
+    directory: PathBuf,
+    transport: AsyncFileTransport<Tokio1Executor>,
+    sender: SubscriberEmail,
+}
+
+impl FileSinkEmailClient {
+    pub fn new(sender: SubscriberEmail, directory: impl Into<PathBuf>) -> Self {
+        let directory = directory.into();
+        let transport = AsyncFileTransport::new(&directory);
+        Self {
+            directory,
+            transport,
+            sender,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for FileSinkEmailClient {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader<'_>],
+    ) -> Result<(), anyhow::Error> {
+        let message = build_message(
+            &self.sender,
+            recipient,
+            subject,
+            html_content,
+            text_content,
+            headers,
+        )?;
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .context("Failed to create the email sink directory.")?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailSender, FileSinkEmailClient};
+    use claims::assert_ok;
+    use fake::faker::internet::en::SafeEmail;
+    use fake::Fake;
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_email_writes_an_eml_file_to_the_directory() {
+        // Arrange
+        let directory = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let email_client = FileSinkEmailClient::new(email(), &directory);
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), "Subject line", "<p>Html body</p>", "Text body")
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let content = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(content.contains("Subject: Subject line"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,91 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailHeader;
+use anyhow::Context;
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::MultiPart;
+use lettre::Message;
+
+// MIME message shared by the backends that speak raw email rather than a provider's HTTP API
+pub fn build_message(
+    sender: &SubscriberEmail,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+    headers: &[EmailHeader<'_>],
+) -> Result<Message, anyhow::Error> {
+    let mut message = Message::builder()
+        .from(sender.as_ref().parse().context("Invalid sender address.")?)
+        .to(recipient
+            .as_ref()
+            .parse()
+            .context("Invalid recipient address.")?)
+        .subject(subject)
+        .multipart(MultiPart::alternative_plain_html(
+            text_content.to_owned(),
+            html_content.to_owned(),
+        ))
+        .context("Failed to build the email message.")?;
+    for header in headers {
+        let name = HeaderName::new_from_ascii(header.name.to_owned())
+            .with_context(|| format!("Invalid email header name {}.", header.name))?;
+        message
+            .headers_mut()
+            .insert_raw(HeaderValue::new(name, header.value.to_owned()));
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::message::build_message;
+    use crate::email_client::EmailHeader;
+
+    fn email(address: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(address.into()).unwrap()
+    }
+
+    #[test]
+    fn message_contains_both_bodies_and_the_extra_headers() {
+        let headers = [EmailHeader {
+            name: "List-Unsubscribe",
+            value: "<https://example.com/unsubscribe>",
+        }];
+        let message = build_message(
+            &email("sender@example.com"),
+            &email("recipient@example.com"),
+            "Subject line",
+            "<p>Html body</p>",
+            "Plain text body",
+            &headers,
+        )
+        .unwrap();
+
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+        assert!(formatted.contains("From: sender@example.com"));
+        assert!(formatted.contains("To: recipient@example.com"));
+        assert!(formatted.contains("Subject: Subject line"));
+        assert!(formatted.contains("List-Unsubscribe: <https://example.com/unsubscribe>"));
+        assert!(formatted.contains("multipart/alternative"));
+        assert!(formatted.contains("Plain text body"));
+        assert!(formatted.contains("<p>Html body</p>"));
+    }
+
+    #[test]
+    fn invalid_header_names_are_rejected() {
+        let headers = [EmailHeader {
+            name: "Not a header",
+            value: "value",
+        }];
+        let outcome = build_message(
+            &email("sender@example.com"),
+            &email("recipient@example.com"),
+            "Subject line",
+            "<p>Html body</p>",
+            "Plain text body",
+            &headers,
+        );
+        claims::assert_err!(outcome);
+    }
+}
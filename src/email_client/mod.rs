@@ -0,0 +1,73 @@
+mod file_sink;
+mod message;
+mod postmark;
+mod smtp;
+
+pub use file_sink::FileSinkEmailClient;
+pub use postmark::PostmarkEmailClient;
+pub use smtp::SmtpEmailClient;
+
+use crate::domain::SubscriberEmail;
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailHeader<'a> {
+    pub name: &'a str,
+    pub value: &'a str,
+}
+
+// A single message of a batch, see `EmailSender::send_email_batch`
+pub struct OutgoingEmail<'a> {
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    pub headers: Vec<EmailHeader<'a>>,
+}
+
+// Every email backend the application can deliver through. The concrete backend is picked at
+// start up from `EmailClientSettings`, the rest of the code only sees a `dyn EmailSender`.
+#[async_trait::async_trait]
+pub trait EmailSender: Send + Sync {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader<'_>],
+    ) -> Result<(), anyhow::Error>;
+
+    async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.send_email_with_headers(recipient, subject, html_content, text_content, &[])
+            .await
+    }
+
+    // Returns one outcome per email, in the same order as `emails`, so callers can retry only the
+    // recipients that failed. Backends without a batch API send the emails one at a time.
+    async fn send_email_batch(
+        &self,
+        emails: &[OutgoingEmail<'_>],
+    ) -> Vec<Result<(), anyhow::Error>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for email in emails {
+            let outcome = self
+                .send_email_with_headers(
+                    email.recipient,
+                    email.subject,
+                    email.html_content,
+                    email.text_content,
+                    &email.headers,
+                )
+                .await;
+            outcomes.push(outcome);
+        }
+        outcomes
+    }
+}
@@ -0,0 +1,412 @@
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailHeader, EmailSender, OutgoingEmail};
+use anyhow::Context;
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+
+// Postmark rejects batches with more than 500 messages
+const MAX_BATCH_SIZE: usize = 500;
+
+// Sends emails through Postmark's `/email` and `/email/batch` JSON APIs
+pub struct PostmarkEmailClient {
+    http_client: Client,
+    base_url: reqwest::Url,
+    sender: SubscriberEmail,
+    authorization_token: Secret<String>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    headers: &'a [EmailHeader<'a>],
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailResponse {
+    error_code: i64,
+    message: String,
+}
+
+impl PostmarkEmailClient {
+    pub fn new(
+        sender: SubscriberEmail,
+        base_url: reqwest::Url,
+        authorization_token: Secret<String>,
+        timeout: std::time::Duration,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        PostmarkEmailClient {
+            http_client,
+            base_url,
+            sender,
+            authorization_token,
+        }
+    }
+
+    fn endpoint(&self, path: &str) -> reqwest::Url {
+        self.base_url.join(path).unwrap_or_else(|_| {
+            panic!(
+                "Unable to add path {} to base url {}",
+                path,
+                self.base_url.as_str()
+            )
+        })
+    }
+
+    fn request<'a>(&'a self, email: &'a OutgoingEmail<'a>) -> SendEmailRequest<'a> {
+        SendEmailRequest {
+            from: self.sender.as_ref(),
+            to: email.recipient.as_ref(),
+            subject: email.subject,
+            html_body: email.html_content,
+            text_body: email.text_content,
+            headers: &email.headers,
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(n_emails = emails.len()))]
+    async fn send_batch_chunk(
+        &self,
+        emails: &[OutgoingEmail<'_>],
+    ) -> Result<Vec<Result<(), anyhow::Error>>, anyhow::Error> {
+        let request_body: Vec<_> = emails.iter().map(|email| self.request(email)).collect();
+        let responses: Vec<SendEmailResponse> = self
+            .http_client
+            .post(self.endpoint("email/batch"))
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("Failed to parse the batch response.")?;
+        // Postmark answers with one entry per message, in the order they were submitted
+        if responses.len() != emails.len() {
+            anyhow::bail!(
+                "Expected {} entries in the batch response, got {}.",
+                emails.len(),
+                responses.len()
+            );
+        }
+        let outcomes = responses
+            .into_iter()
+            .map(|response| match response.error_code {
+                0 => Ok(()),
+                error_code => Err(anyhow::anyhow!(
+                    "Postmark rejected the message (error code {}): {}",
+                    error_code,
+                    response.message
+                )),
+            })
+            .collect();
+        Ok(outcomes)
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailSender for PostmarkEmailClient {
+    async fn send_email_with_headers(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        headers: &[EmailHeader<'_>],
+    ) -> Result<(), anyhow::Error> {
+        let url = self.endpoint("email");
+        let request_body = SendEmailRequest {
+            from: self.sender.as_ref(),
+            to: recipient.as_ref(),
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+            headers,
+        };
+        let _client_builder = self
+            .http_client
+            .post(url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn send_email_batch(
+        &self,
+        emails: &[OutgoingEmail<'_>],
+    ) -> Vec<Result<(), anyhow::Error>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            match self.send_batch_chunk(chunk).await {
+                Ok(chunk_outcomes) => outcomes.extend(chunk_outcomes),
+                // Nothing in the chunk went out: every message in it failed for the same reason
+                Err(e) => {
+                    outcomes.extend(chunk.iter().map(|_| Err(anyhow::anyhow!("{:?}", e))));
+                }
+            }
+        }
+        outcomes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailHeader, EmailSender, OutgoingEmail, PostmarkEmailClient};
+    use claims::{assert_err, assert_ok};
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use fake::{Fake, Faker};
+    use secrecy::Secret;
+    use wiremock::matchers::{any, body_partial_json, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                dbg!(&body);
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+    // Test Helpers
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+    fn email_client(url: reqwest::Url) -> PostmarkEmailClient {
+        PostmarkEmailClient::new(
+            email(),
+            url,
+            Secret::new(Faker.fake()),
+            std::time::Duration::from_millis(20),
+        )
+    }
+    fn set_delay_for_response(seconds: u64) -> ResponseTemplate {
+        let delay = std::time::Duration::from_secs(seconds);
+        ResponseTemplate::new(200).set_delay(delay)
+    }
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = email_client(url);
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(method("POST"))
+            .and(path("/email"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        // Mock expectations are checked on drop
+    }
+
+    #[tokio::test]
+    async fn send_email_with_headers_forwards_the_headers() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = email_client(url);
+
+        Mock::given(path("/email"))
+            .and(body_partial_json(serde_json::json!({
+                "Headers": [{"Name": "List-Unsubscribe", "Value": "<https://example.com>"}]
+            })))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let headers = [EmailHeader {
+            name: "List-Unsubscribe",
+            value: "<https://example.com>",
+        }];
+        let outcome = email_client
+            .send_email_with_headers(&email(), &subject(), &content(), &content(), &headers)
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = email_client(url);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = email_client(url);
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_long() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = email_client(url);
+        let response = set_delay_for_response(180);
+
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_reports_the_outcome_of_each_message() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = email_client(url);
+        let (subject, content) = (subject(), content());
+        let recipients = [email(), email()];
+        let emails: Vec<_> = recipients
+            .iter()
+            .map(|recipient| OutgoingEmail {
+                recipient,
+                subject: &subject,
+                html_content: &content,
+                text_content: &content,
+                headers: vec![],
+            })
+            .collect();
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(method("POST"))
+            .and(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"ErrorCode": 0, "Message": "OK"},
+                {"ErrorCode": 406, "Message": "Inactive recipient"}
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcomes = email_client.send_email_batch(&emails).await;
+
+        // Assert
+        assert_eq!(outcomes.len(), 2);
+        assert_ok!(&outcomes[0]);
+        assert_err!(&outcomes[1]);
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_fails_every_message_if_the_server_returns_500() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let url = reqwest::Url::parse(&mock_server.uri()).unwrap();
+        let email_client = email_client(url);
+        let (subject, content) = (subject(), content());
+        let recipients = [email(), email()];
+        let emails: Vec<_> = recipients
+            .iter()
+            .map(|recipient| OutgoingEmail {
+                recipient,
+                subject: &subject,
+                html_content: &content,
+                text_content: &content,
+                headers: vec![],
+            })
+            .collect();
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcomes = email_client.send_email_batch(&emails).await;
+
+        // Assert
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes.iter().all(|outcome| outcome.is_err()));
+    }
+}
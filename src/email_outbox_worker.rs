@@ -0,0 +1,223 @@
+use crate::configuration::WorkerSettings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailSender;
+use crate::issue_delivery_worker::{retry_delay, ExecutionOutcome};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct OutboxEmail {
+    email_id: Uuid,
+    recipient: String,
+    subject: String,
+    html_content: String,
+    text_content: String,
+    n_attempts: i16,
+}
+
+// Writes an email to the outbox as part of `transaction`, it is only sent once that is committed
+#[tracing::instrument(name = "Add an email to the outbox", skip_all)]
+pub async fn enqueue_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_outbox (email_id, recipient, subject, html_content, text_content)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        recipient.as_ref(),
+        subject,
+        html_content,
+        text_content
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+// Sends outbox emails one at a time until `shutdown` is cancelled, sharing the polling and retry
+// settings of the newsletter delivery workers
+#[tracing::instrument(skip_all)]
+pub(crate) async fn outbox_loop(
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    worker_settings: WorkerSettings,
+    shutdown: CancellationToken,
+) {
+    while !shutdown.is_cancelled() {
+        let wait =
+            match try_execute_outbox_task(&pool, email_client.as_ref(), &worker_settings).await {
+                Ok(ExecutionOutcome::TaskCompleted) => continue,
+                Ok(ExecutionOutcome::EmptyQueue) => worker_settings.poll_interval(),
+                Err(_) => worker_settings.error_backoff(),
+            };
+        tokio::select! {
+            _ = shutdown.cancelled() => {}
+            _ = tokio::time::sleep(wait) => {}
+        }
+    }
+    tracing::info!("Outbox worker has shut down.");
+}
+
+#[tracing::instrument(skip_all, fields(email_id=tracing::field::Empty), err)]
+pub async fn try_execute_outbox_task(
+    pool: &PgPool,
+    email_client: &dyn EmailSender,
+    worker_settings: &WorkerSettings,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let (mut transaction, email) = match dequeue_email(pool).await? {
+        Some(dequeued) => dequeued,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    tracing::Span::current().record("email_id", tracing::field::display(email.email_id));
+    let outcome = match SubscriberEmail::parse(email.recipient.clone()) {
+        Ok(recipient) => email_client
+            .send_email(
+                &recipient,
+                &email.subject,
+                &email.html_content,
+                &email.text_content,
+            )
+            .await
+            .context("Failed to send an email from the outbox."),
+        Err(e) => Err(anyhow::anyhow!(e)),
+    };
+    match outcome {
+        Ok(()) => delete_email(&mut transaction, email.email_id).await?,
+        Err(e) => {
+            let n_attempts = email.n_attempts + 1;
+            if n_attempts < worker_settings.max_attempts {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    recipient = %email.recipient,
+                    n_attempts,
+                    "Failed to send an email from the outbox -- Retrying later."
+                );
+                let delay = retry_delay(worker_settings, n_attempts);
+                let next_attempt_at = Utc::now() + chrono::Duration::from_std(delay)?;
+                retry_email(
+                    &mut transaction,
+                    email.email_id,
+                    n_attempts,
+                    next_attempt_at,
+                    &e.to_string(),
+                )
+                .await?;
+            } else {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    recipient = %email.recipient,
+                    "Failed to send an email from the outbox after {} attempts -- Giving up.",
+                    n_attempts
+                );
+                fail_email(
+                    &mut transaction,
+                    email.email_id,
+                    n_attempts,
+                    &format!("{:?}", e),
+                )
+                .await?;
+            }
+        }
+    }
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+// The email stays locked until the transaction is committed, concurrent workers skip it
+#[tracing::instrument(skip_all)]
+async fn dequeue_email(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, OutboxEmail)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let email = sqlx::query_as!(
+        OutboxEmail,
+        r#"
+        SELECT email_id, recipient, subject, html_content, text_content, n_attempts
+        FROM email_outbox
+        WHERE failed_at IS NULL AND next_attempt_at <= now()
+        ORDER BY next_attempt_at
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    Ok(email.map(|email| (transaction, email)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_email(
+    transaction: &mut PgTransaction,
+    email_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!("DELETE FROM email_outbox WHERE email_id = $1", email_id)
+        .execute(transaction)
+        .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn retry_email(
+    transaction: &mut PgTransaction,
+    email_id: Uuid,
+    n_attempts: i16,
+    next_attempt_at: chrono::DateTime<Utc>,
+    last_error: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE email_outbox
+        SET
+            n_attempts = $2,
+            next_attempt_at = $3,
+            last_error = $4
+        WHERE email_id = $1
+        "#,
+        email_id,
+        n_attempts,
+        next_attempt_at,
+        last_error
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn fail_email(
+    transaction: &mut PgTransaction,
+    email_id: Uuid,
+    n_attempts: i16,
+    error: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE email_outbox
+        SET
+            n_attempts = $2,
+            last_error = $3,
+            failed_at = now()
+        WHERE email_id = $1
+        "#,
+        email_id,
+        n_attempts,
+        error
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,291 @@
+// Cleans up the HTML body of an issue before it is published. Markup outside of an email-safe
+// allowlist is dropped (keeping its text) by ammonia, and anything that would run a script or
+// leave a reader with a dead link is reported first so the author can fix it.
+use ammonia::{Builder, UrlRelative};
+use html5ever::tendril::StrTendril;
+use html5ever::tokenizer::states::RawKind;
+use html5ever::tokenizer::{
+    BufferQueue, TagKind, Token, TokenSink, TokenSinkResult, Tokenizer, TokenizerOpts,
+};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+const ALLOWED_ELEMENTS: [&str; 42] = [
+    "a",
+    "abbr",
+    "article",
+    "b",
+    "blockquote",
+    "br",
+    "caption",
+    "code",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "em",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "i",
+    "img",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "s",
+    "section",
+    "small",
+    "span",
+    "strong",
+    "table",
+    "tbody",
+    "td",
+    "th",
+    "thead",
+    "tr",
+    "ul",
+];
+// Elements whose content is dropped along with them
+const DROPPED_ELEMENTS: [&str; 10] = [
+    "head", "title", "script", "style", "iframe", "object", "embed", "form", "noscript", "template",
+];
+const GLOBAL_ATTRIBUTES: [&str; 4] = ["title", "align", "dir", "lang"];
+const TAG_ATTRIBUTES: [(&str, &[&str]); 6] = [
+    ("a", &["href", "name", "target"]),
+    ("img", &["src", "alt", "width", "height"]),
+    (
+        "table",
+        &["width", "border", "cellpadding", "cellspacing", "bgcolor"],
+    ),
+    ("td", &["colspan", "rowspan", "width", "valign", "bgcolor"]),
+    ("th", &["colspan", "rowspan", "width", "valign", "bgcolor"]),
+    ("ol", &["start", "type"]),
+];
+// The attributes that are kept and followed from the reader's mail client
+const URL_ATTRIBUTES: [(&str, &str); 2] = [("a", "href"), ("img", "src")];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HtmlProblem {
+    Script,
+    EventHandler(String),
+    UnsafeUrl(String),
+    RelativeUrl(String),
+}
+
+impl std::fmt::Display for HtmlProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Script => write!(f, "Newsletters cannot contain <script> elements."),
+            Self::EventHandler(name) => write!(
+                f,
+                "The {} attribute is not allowed, newsletters cannot run scripts.",
+                name
+            ),
+            Self::UnsafeUrl(url) => write!(f, "{} is not allowed as a link.", url),
+            Self::RelativeUrl(url) => write!(
+                f,
+                "The relative link \"{}\" will not resolve in a mail client, use an absolute \
+                https:// URL instead.",
+                url
+            ),
+        }
+    }
+}
+
+// Returns the sanitized HTML, or every problem found so they can all be fixed in one go
+pub fn sanitize_html(html: &str) -> Result<String, Vec<HtmlProblem>> {
+    let problems = find_problems(html);
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+    Ok(allowlist().clean(html).to_string())
+}
+
+// Rewrites the `href` of every link in HTML that went through `sanitize_html`, links for which
+// `rewrite` returns `None` are left untouched.
+pub fn rewrite_links<F>(html: &str, rewrite: F) -> String
+where
+    F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+{
+    allowlist()
+        .attribute_filter(move |element, attribute, value| {
+            if element == "a" && attribute == "href" {
+                if let Some(rewritten) = rewrite(value) {
+                    return Some(rewritten.into());
+                }
+            }
+            Some(value.into())
+        })
+        .clean(html)
+        .to_string()
+}
+
+// `style` is left out on purpose: CSS escapes and comments make it impossible to keep
+// `url(...)` and friends out with a blacklist.
+fn allowlist() -> Builder<'static> {
+    let mut builder = Builder::empty();
+    builder
+        .tags(HashSet::from(ALLOWED_ELEMENTS))
+        .clean_content_tags(HashSet::from(DROPPED_ELEMENTS))
+        .generic_attributes(HashSet::from(GLOBAL_ATTRIBUTES))
+        .tag_attributes(
+            TAG_ATTRIBUTES
+                .iter()
+                .map(|(element, attributes)| (*element, attributes.iter().copied().collect()))
+                .collect::<HashMap<_, _>>(),
+        )
+        .url_schemes(HashSet::from(["http", "https", "mailto", "tel"]))
+        // Merge fields such as `{{ unsubscribe_url }}` look relative, actual relative links are
+        // reported before getting here
+        .url_relative(UrlRelative::PassThrough)
+        .link_rel(None);
+    builder
+}
+
+fn find_problems(html: &str) -> Vec<HtmlProblem> {
+    let input = BufferQueue::default();
+    input.push_back(StrTendril::from_slice(html));
+    let tokenizer = Tokenizer::new(ProblemFinder::default(), TokenizerOpts::default());
+    let _ = tokenizer.feed(&input);
+    tokenizer.end();
+    tokenizer.sink.problems.into_inner()
+}
+
+// Looks at the start tags as a browser would see them, entities decoded and names lowercased
+#[derive(Default)]
+struct ProblemFinder {
+    problems: RefCell<Vec<HtmlProblem>>,
+}
+
+impl ProblemFinder {
+    fn report(&self, problem: HtmlProblem) {
+        let mut problems = self.problems.borrow_mut();
+        if !problems.contains(&problem) {
+            problems.push(problem);
+        }
+    }
+}
+
+impl TokenSink for ProblemFinder {
+    type Handle = ();
+
+    fn process_token(&self, token: Token, _line_number: u64) -> TokenSinkResult<()> {
+        let tag = match token {
+            Token::TagToken(tag) if tag.kind == TagKind::StartTag => tag,
+            _ => return TokenSinkResult::Continue,
+        };
+        if &*tag.name == "script" {
+            self.report(HtmlProblem::Script);
+        }
+        for attribute in &tag.attrs {
+            let name = &*attribute.name.local;
+            if name.starts_with("on") {
+                self.report(HtmlProblem::EventHandler(name.into()));
+            } else if URL_ATTRIBUTES.contains(&(&*tag.name, name)) {
+                if let Err(problem) = check_url(&attribute.value) {
+                    self.report(problem);
+                }
+            }
+        }
+        // The content of scripts and style sheets is not markup
+        match &*tag.name {
+            "script" => TokenSinkResult::RawData(RawKind::ScriptData),
+            "style" => TokenSinkResult::RawData(RawKind::Rawtext),
+            _ => TokenSinkResult::Continue,
+        }
+    }
+}
+
+// Links are followed from the reader's mail client, so they must be absolute
+fn check_url(url: &str) -> Result<(), HtmlProblem> {
+    let url = url.trim();
+    let lowercase: String = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    if ["javascript:", "vbscript:", "data:"]
+        .iter()
+        .any(|scheme| lowercase.starts_with(scheme))
+    {
+        return Err(HtmlProblem::UnsafeUrl(url.into()));
+    }
+    // Merge fields such as `{{ unsubscribe_url }}` are absolute once rendered
+    let is_absolute = ["https://", "http://", "mailto:", "tel:", "#", "{{"]
+        .iter()
+        .any(|prefix| lowercase.starts_with(prefix));
+    if is_absolute {
+        Ok(())
+    } else {
+        Err(HtmlProblem::RelativeUrl(url.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::html_sanitizer::{sanitize_html, HtmlProblem};
+
+    #[test]
+    fn allowed_markup_is_kept() {
+        let html = r#"<h1>Title</h1><p title="Greeting">Hi <a href="https://example.com?a=1&amp;b=2">there</a><br></p>"#;
+        assert_eq!(sanitize_html(html).unwrap(), html);
+    }
+
+    #[test]
+    fn style_attributes_are_dropped() {
+        let html = r#"<p style="background:u\rl(https://tracker)">a</p><p style="background:ur/**/l(https://tracker)">b</p>"#;
+        assert_eq!(sanitize_html(html).unwrap(), "<p>a</p><p>b</p>");
+    }
+
+    #[test]
+    fn markup_outside_of_the_allowlist_is_dropped() {
+        let html = r#"<style>p {}</style><font color="red">Hi</font> <p class="x" id="y">there<iframe src="https://example.com">x</iframe></p><!-- note -->"#;
+        assert_eq!(sanitize_html(html).unwrap(), "Hi <p>there</p>");
+    }
+
+    #[test]
+    fn broken_markup_is_balanced() {
+        assert_eq!(
+            sanitize_html("<p><b>bold</p></i>1 < 2").unwrap(),
+            "<p><b>bold</b></p><b>1 &lt; 2</b>"
+        );
+    }
+
+    #[test]
+    fn scripts_and_event_handlers_are_reported() {
+        let problems =
+            sanitize_html(r#"<script>alert(1)</script><img src="https://a.b/c.png" onerror="x">"#)
+                .unwrap_err();
+        assert_eq!(
+            problems,
+            vec![
+                HtmlProblem::Script,
+                HtmlProblem::EventHandler("onerror".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn unsafe_and_relative_links_are_reported() {
+        let problems = sanitize_html(
+            r#"<a href="JavaScript&#58;alert(1)">a</a><a href="/archive">b</a><a href="{{ unsubscribe_url }}">c</a><a href="mailto:me@example.com">d</a>"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            problems,
+            vec![
+                HtmlProblem::UnsafeUrl("JavaScript:alert(1)".into()),
+                HtmlProblem::RelativeUrl("/archive".into())
+            ]
+        );
+    }
+}
@@ -0,0 +1,79 @@
+// Derives the plain-text alternative of a newsletter issue from its HTML body: headings and list
+// items get a Markdown-like marker and links become numbered footnotes listed after the text.
+
+// Plain-text emails are conventionally wrapped to fit in 78 columns (RFC 5322)
+const TEXT_WIDTH: usize = 78;
+
+pub fn html_to_text(html: &str) -> String {
+    html2text::config::plain()
+        .allow_width_overflow()
+        .no_link_wrapping()
+        .string_from_read(html.as_bytes(), TEXT_WIDTH)
+        .map(|text| text.trim_end().to_owned())
+        // Nothing is left to fail on when reading from memory and the width may overflow
+        .unwrap_or_else(|e| {
+            tracing::error!(
+                error.message = %e,
+                "Failed to derive the plain-text body of an issue from its HTML."
+            );
+            String::new()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::html_to_text::html_to_text;
+
+    #[test]
+    fn tags_are_stripped_and_paragraphs_separated() {
+        assert_eq!(
+            html_to_text("<p>Hello <b>there</b>,\n   friend.</p><p>Second&nbsp;&amp; last</p>"),
+            "Hello **there**, friend.\n\nSecond\u{a0}& last"
+        );
+    }
+
+    #[test]
+    fn headings_are_marked() {
+        assert_eq!(
+            html_to_text("<h1>Title</h1><p>Intro</p><h2>Section</h2>text"),
+            "# Title\n\nIntro\n\n## Section\n\ntext"
+        );
+    }
+
+    #[test]
+    fn links_become_footnotes() {
+        assert_eq!(
+            html_to_text(
+                r#"<p>Read <a href="https://example.com/a?x=1&amp;y=2">this</a> and <a href='#top'>that</a>.</p>"#
+            ),
+            "Read [this][1] and [that][2].\n\n[1]: https://example.com/a?x=1&y=2\n[2]: #top"
+        );
+    }
+
+    #[test]
+    fn lists_are_rendered_with_markers() {
+        assert_eq!(
+            html_to_text("<p>Todo:</p><ul><li>one</li><li>two<ol><li>a</li><li>b</li></ol></li></ul><p>Done</p>"),
+            "Todo:\n* one\n* two\n  1. a\n  2. b\n\nDone"
+        );
+    }
+
+    #[test]
+    fn scripts_styles_and_comments_are_dropped() {
+        assert_eq!(
+            html_to_text(
+                "<head><title>T</title><style>p { color: red; }</style></head>\
+                <!-- hidden --><p>Visible<script>alert(1)</script></p>"
+            ),
+            "Visible"
+        );
+    }
+
+    #[test]
+    fn merge_field_placeholders_are_kept() {
+        assert_eq!(
+            html_to_text(r#"<p>Hi {{ name }}, <a href="{{ unsubscribe_url }}">leave</a></p>"#),
+            "Hi {{ name }}, [leave][1]\n\n[1]: {{ unsubscribe_url }}"
+        );
+    }
+}
@@ -18,6 +18,7 @@ impl PgHasArrayType for HeaderPairRecord {
         sqlx::postgres::PgTypeInfo::with_name("_header_pair")
     }
 }
+#[allow(clippy::large_enum_variant)]
 pub enum NextAction {
     StartProcessing(Transaction<'static, Postgres>),
     ReturnSavedResponse(HttpResponse),
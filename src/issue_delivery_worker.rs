@@ -1,10 +1,20 @@
-use crate::configuration::Settings;
+use crate::configuration::{Settings, WorkerSettings};
+use crate::domain::{MergeFields, NewsletterTemplate, SubscriberEmail};
+use crate::email_client::{EmailHeader, EmailSender, OutgoingEmail};
+use crate::email_outbox_worker::outbox_loop;
+use crate::html_sanitizer::rewrite_links;
+use crate::link_signer::LinkSigner;
 use crate::startup::get_connection_pool;
-use crate::{domain::SubscriberEmail, email_client::EmailClient};
+use anyhow::Context;
+use chrono::Utc;
+use rand::Rng;
 use sqlx::{PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::time::Sleep;
-use tracing::{field::display, Span};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tracing::Span;
 use uuid::Uuid;
 
 type PgTransaction = Transaction<'static, Postgres>;
@@ -13,6 +23,8 @@ pub struct Newsletter {
     pub title: String,
     pub html_content: String,
     pub text_content: String,
+    // Kept so issues written in Markdown can be edited in Markdown again
+    pub markdown_content: Option<String>,
 }
 
 pub enum ExecutionOutcome {
@@ -20,108 +32,334 @@ pub enum ExecutionOutcome {
     EmptyQueue,
 }
 
-pub async fn run_worker_until_stopped(configuration: Settings) -> Result<(), anyhow::Error> {
+enum DeliveryOutcome {
+    Delivered,
+    Failed,
+    // The subscriber is gone, the issue has one recipient less
+    Dropped,
+}
+
+struct Subscriber {
+    id: Uuid,
+    name: String,
+}
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_attempts: i16,
+}
+
+// A task whose recipient checked out, along with the personalised content we are about to send
+struct Delivery<'a> {
+    task: &'a DeliveryTask,
+    recipient: SubscriberEmail,
+    title: &'a str,
+    html_content: String,
+    text_content: String,
+    list_unsubscribe: String,
+}
+
+impl<'a> Delivery<'a> {
+    // Fails if the issue content is not a valid template, which is checked when it is published
+    fn new(
+        task: &'a DeliveryTask,
+        recipient: SubscriberEmail,
+        issue: &'a Newsletter,
+        subscriber: &Subscriber,
+        link_signer: &LinkSigner,
+        worker_settings: &WorkerSettings,
+    ) -> Result<Self, String> {
+        let unsubscribe_url = link_signer.unsubscribe_url(subscriber.id);
+        let preferences_url = link_signer.preferences_url(subscriber.id);
+        let merge_fields = MergeFields {
+            name: &subscriber.name,
+            email: recipient.as_ref(),
+            unsubscribe_url: unsubscribe_url.as_str(),
+        };
+        let mut html_content =
+            NewsletterTemplate::parse(&issue.html_content)?.render_html(&merge_fields);
+        if worker_settings.track_clicks {
+            let (link_signer, unsubscribe_url) = (link_signer.clone(), unsubscribe_url.clone());
+            let (newsletter_issue_id, subscriber_id) = (task.newsletter_issue_id, subscriber.id);
+            html_content = add_click_tracking(&html_content, move |destination| {
+                // Unsubscribing must keep working without going through our redirect
+                (*destination != unsubscribe_url).then(|| {
+                    link_signer.click_tracking_url(newsletter_issue_id, subscriber_id, destination)
+                })
+            });
+        }
+        html_content.push_str(&format!(
+            "<p><a href=\"{}\">Manage your subscription</a> | <a href=\"{}\">Unsubscribe</a></p>",
+            preferences_url, unsubscribe_url
+        ));
+        if worker_settings.track_opens {
+            let open_tracking_url =
+                link_signer.open_tracking_url(task.newsletter_issue_id, subscriber.id);
+            html_content.push_str(&format!(
+                r#"<img src="{}" width="1" height="1" alt="">"#,
+                open_tracking_url
+            ));
+        }
+        let text_content = format!(
+            "{}\n\nManage your subscription: {}\nUnsubscribe: {}",
+            NewsletterTemplate::parse(&issue.text_content)?.render_text(&merge_fields),
+            preferences_url,
+            unsubscribe_url
+        );
+        // RFC 8058 one-click unsubscribe: mail clients POST to the link on the user's behalf
+        let list_unsubscribe = format!("<{}>", unsubscribe_url);
+        Ok(Self {
+            task,
+            recipient,
+            title: &issue.title,
+            html_content,
+            text_content,
+            list_unsubscribe,
+        })
+    }
+
+    fn email(&self) -> OutgoingEmail<'_> {
+        OutgoingEmail {
+            recipient: &self.recipient,
+            subject: self.title,
+            html_content: &self.html_content,
+            text_content: &self.text_content,
+            headers: vec![
+                EmailHeader {
+                    name: "List-Unsubscribe",
+                    value: &self.list_unsubscribe,
+                },
+                EmailHeader {
+                    name: "List-Unsubscribe-Post",
+                    value: "List-Unsubscribe=One-Click",
+                },
+            ],
+        }
+    }
+}
+
+// Rewrites the `href` of every web link with `tracked_url`, other links (e.g. `mailto:`) and links
+// for which `tracked_url` returns `None` are left untouched.
+fn add_click_tracking(
+    html: &str,
+    tracked_url: impl Fn(&reqwest::Url) -> Option<reqwest::Url> + Send + Sync + 'static,
+) -> String {
+    rewrite_links(html, move |href| {
+        reqwest::Url::parse(href.trim())
+            .ok()
+            .filter(|url| url.scheme() == "https" || url.scheme() == "http")
+            .and_then(|url| tracked_url(&url))
+            .map(String::from)
+    })
+}
+
+// Runs `worker.pool_size` delivery loops, and one loop sending the email outbox, until `shutdown`
+// is cancelled. Each loop only checks for shutdown between batches, so in-flight sends are
+// finished and their transaction (and row locks) is committed before the worker exits.
+pub async fn run_worker_until_stopped(
+    configuration: Settings,
+    shutdown: CancellationToken,
+) -> Result<(), anyhow::Error> {
     let connection_pool = get_connection_pool(&configuration.database);
     let email_client = configuration.email_client.client();
+    let link_signer = configuration.application.link_signer();
 
-    worker_loop(connection_pool, email_client).await
-}
-async fn worker_loop(pool: PgPool, email_client: EmailClient) -> Result<(), anyhow::Error> {
-    loop {
-        match try_execute_task(&pool, &email_client).await {
-            Ok(ExecutionOutcome::TaskCompleted) => {}
-            Ok(ExecutionOutcome::EmptyQueue) => sleep(10).await,
-            Err(_) => sleep(1).await,
+    let mut workers = JoinSet::new();
+    for worker_id in 0..configuration.worker.pool_size {
+        workers.spawn(worker_loop(
+            worker_id,
+            connection_pool.clone(),
+            email_client.clone(),
+            configuration.worker.clone(),
+            link_signer.clone(),
+            shutdown.clone(),
+        ));
+    }
+    // Transactional emails are few, a single loop keeps up with them
+    workers.spawn(outbox_loop(
+        connection_pool.clone(),
+        email_client.clone(),
+        configuration.worker.clone(),
+        shutdown.clone(),
+    ));
+    while let Some(outcome) = workers.join_next().await {
+        if let Err(e) = outcome {
+            // Dropping the set would abort the other loops mid-send, they are stopped like on
+            // shutdown instead
+            shutdown.cancel();
+            while workers.join_next().await.is_some() {}
+            return Err(e.into());
         }
-        if try_execute_task(&pool, &email_client).await.is_err() {
-            tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    Ok(())
+}
+
+#[tracing::instrument(skip_all, fields(worker_id = worker_id))]
+async fn worker_loop(
+    worker_id: u16,
+    pool: PgPool,
+    email_client: Arc<dyn EmailSender>,
+    worker_settings: WorkerSettings,
+    link_signer: LinkSigner,
+    shutdown: CancellationToken,
+) {
+    while !shutdown.is_cancelled() {
+        let wait =
+            match try_execute_task(&pool, email_client.as_ref(), &worker_settings, &link_signer)
+                .await
+            {
+                Ok(ExecutionOutcome::TaskCompleted) => continue,
+                Ok(ExecutionOutcome::EmptyQueue) => worker_settings.poll_interval(),
+                Err(_) => worker_settings.error_backoff(),
+            };
+        tokio::select! {
+            _ = shutdown.cancelled() => {}
+            _ = tokio::time::sleep(wait) => {}
         }
     }
+    tracing::info!("Delivery worker has shut down.");
 }
-#[tracing::instrument(
-    skip_all,
-    fields(
-        newsletter_issue_id=tracing::field::Empty,
-        subscriber_email=tracing::field::Empty,
-    ),
-    err
-)]
+
+#[tracing::instrument(skip_all, fields(n_tasks=tracing::field::Empty), err)]
 pub async fn try_execute_task(
     pool: &PgPool,
-    email_client: &EmailClient,
+    email_client: &dyn EmailSender,
+    worker_settings: &WorkerSettings,
+    link_signer: &LinkSigner,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
-    let task = dequeue_task(pool).await?;
-    if task.is_none() {
+    let (mut transaction, tasks) = dequeue_tasks(pool, worker_settings.batch_size).await?;
+    if tasks.is_empty() {
         return Ok(ExecutionOutcome::EmptyQueue);
     }
-    let (transaction, issue_id, email) = task.unwrap();
-    Span::current()
-        .record("newsletter_issue_id", &display(issue_id))
-        .record("subscriber_email", &display(&email));
-    match SubscriberEmail::parse(email.clone()) {
-        Ok(email) => {
-            let newsletter_issue = get_issue(pool, issue_id).await?;
-            if let Err(e) = email_client
-                .send_email(
-                    &email,
-                    &newsletter_issue.title,
-                    &newsletter_issue.html_content,
-                    &newsletter_issue.text_content,
-                )
-                .await
-            {
+    Span::current().record("n_tasks", tasks.len());
+    let issues = get_issues(pool, &tasks).await?;
+    let subscribers = get_subscribers(pool, &tasks).await?;
+
+    let mut deliveries = Vec::with_capacity(tasks.len());
+    for task in &tasks {
+        let recipient = match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Ok(recipient) => recipient,
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping a confirmed subscriber. \
+                    Their stored contact detailed are invalid.");
+                fail_task(&mut transaction, task, task.n_attempts, &e).await?;
+                continue;
+            }
+        };
+        let issue = issues
+            .get(&task.newsletter_issue_id)
+            .context("The newsletter issue of a queued delivery is missing.")?;
+        let subscriber = match subscribers.get(&task.subscriber_email) {
+            Some(subscriber) => subscriber,
+            None => {
+                tracing::info!(
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Dropping a queued delivery. \
+                    No confirmed subscriber is registered with this address anymore.");
+                delete_task(&mut transaction, task, DeliveryOutcome::Dropped).await?;
+                continue;
+            }
+        };
+        match Delivery::new(
+            task,
+            recipient,
+            issue,
+            subscriber,
+            link_signer,
+            worker_settings,
+        ) {
+            Ok(delivery) => deliveries.push(delivery),
+            Err(e) => {
                 tracing::error!(
-                    error.casue_chain = ?e,
                     error.message = %e,
-                    "Failed to deliver issue to a confirmed subscriber -- Skipping."
-                )
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping a confirmed subscriber. \
+                    The newsletter issue cannot be rendered.");
+                fail_task(&mut transaction, task, task.n_attempts, &e).await?;
             }
         }
-        Err(e) => {
-            tracing::error!(
-                error.cause_chain = ?e,
-                error.message = %e,
-                "Skipping a confirmed subscriber. \
-                Their stored contact detailed are invalid.");
+    }
+
+    let emails: Vec<_> = deliveries.iter().map(Delivery::email).collect();
+    let outcomes = email_client.send_email_batch(&emails).await;
+    for (delivery, outcome) in deliveries.iter().zip(outcomes) {
+        let task = delivery.task;
+        match outcome {
+            Ok(()) => delete_task(&mut transaction, task, DeliveryOutcome::Delivered).await?,
+            Err(e) => {
+                let n_attempts = task.n_attempts + 1;
+                if n_attempts < worker_settings.max_attempts {
+                    tracing::warn!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        newsletter_issue_id = %task.newsletter_issue_id,
+                        subscriber_email = %task.subscriber_email,
+                        n_attempts,
+                        "Failed to deliver issue to a confirmed subscriber -- Retrying later."
+                    );
+                    let delay = retry_delay(worker_settings, n_attempts);
+                    retry_task(&mut transaction, task, n_attempts, delay, &e.to_string()).await?;
+                } else {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        newsletter_issue_id = %task.newsletter_issue_id,
+                        subscriber_email = %task.subscriber_email,
+                        "Failed to deliver issue to a confirmed subscriber after {} attempts -- \
+                        Moving it to the failed deliveries.",
+                        n_attempts
+                    );
+                    fail_task(&mut transaction, task, n_attempts, &format!("{:?}", e)).await?;
+                }
+            }
         }
     }
-    delete_task(transaction, issue_id, &email).await?;
+    transaction.commit().await?;
     Ok(ExecutionOutcome::TaskCompleted)
 }
 
+// Lock up to `batch_size` due deliveries, skipping issues scheduled for later. They stay locked
+// until the transaction is committed, so concurrent workers skip them and pick up another batch.
 #[tracing::instrument(skip_all)]
-async fn dequeue_task(
+async fn dequeue_tasks(
     pool: &PgPool,
-) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
+    batch_size: u16,
+) -> Result<(PgTransaction, Vec<DeliveryTask>), anyhow::Error> {
     let mut transaction = pool.begin().await?;
-    let r = sqlx::query!(
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
         r#"
-            SELECT newsletter_issue_id, subscriber_email
-            FROM issue_delivery_queue
-            FOR UPDATE
+            SELECT q.newsletter_issue_id, q.subscriber_email, q.n_attempts
+            FROM issue_delivery_queue q
+            JOIN newsletter_issues i ON i.newsletter_issue_id = q.newsletter_issue_id
+            WHERE
+                q.next_attempt_at <= now() AND
+                (i.scheduled_for IS NULL OR i.scheduled_for <= now())
+            FOR UPDATE OF q
             SKIP LOCKED
-            LIMIT 1
-        "#
+            LIMIT $1
+        "#,
+        i64::from(batch_size)
     )
-    .fetch_optional(&mut transaction)
+    .fetch_all(&mut transaction)
     .await?;
 
-    if let Some(r) = r {
-        Ok(Some((
-            transaction,
-            r.newsletter_issue_id,
-            r.subscriber_email,
-        )))
-    } else {
-        Ok(None)
-    }
+    Ok((transaction, tasks))
 }
 
 #[tracing::instrument(skip_all)]
 async fn delete_task(
-    mut transaction: PgTransaction,
-    issue_id: Uuid,
-    email: &str,
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    outcome: DeliveryOutcome,
 ) -> Result<(), anyhow::Error> {
     sqlx::query!(
         r#"
@@ -130,32 +368,240 @@ async fn delete_task(
         newsletter_issue_id = $1 AND
         subscriber_email = $2
         "#,
-        issue_id,
-        email
+        task.newsletter_issue_id,
+        task.subscriber_email
     )
-    .execute(&mut transaction)
+    .execute(&mut *transaction)
+    .await?;
+    record_delivery_outcome(transaction, task.newsletter_issue_id, outcome).await
+}
+
+// Keep the per-issue counters in the same transaction as the queue so they never drift from it.
+#[tracing::instrument(skip_all)]
+async fn record_delivery_outcome(
+    transaction: &mut PgTransaction,
+    newsletter_issue_id: Uuid,
+    outcome: DeliveryOutcome,
+) -> Result<(), anyhow::Error> {
+    let (n_delivered, n_failed, n_dropped) = match outcome {
+        DeliveryOutcome::Delivered => (1, 0, 0),
+        DeliveryOutcome::Failed => (0, 1, 0),
+        DeliveryOutcome::Dropped => (0, 0, 1),
+    };
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            n_delivered = n_delivered + $2,
+            n_failed = n_failed + $3,
+            n_recipients = n_recipients - $4,
+            delivery_completed_at = CASE
+                WHEN n_delivered + $2 + n_failed + $3 >= n_recipients - $4 THEN now()
+                ELSE NULL
+            END
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        n_delivered,
+        n_failed,
+        n_dropped
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn retry_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    n_attempts: i16,
+    delay: Duration,
+    last_error: &str,
+) -> Result<(), anyhow::Error> {
+    let next_attempt_at = Utc::now() + chrono::Duration::from_std(delay)?;
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_attempts = $3,
+            next_attempt_at = $4,
+            last_error = $5
+        WHERE
+        newsletter_issue_id = $1 AND
+        subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        n_attempts,
+        next_attempt_at,
+        last_error
+    )
+    .execute(transaction)
     .await?;
-    transaction.commit().await?;
     Ok(())
 }
 
+// Permanently failed deliveries are kept around so they can be inspected and requeued from the
+// admin area once the underlying problem is fixed.
 #[tracing::instrument(skip_all)]
-async fn get_issue(pool: &PgPool, issue_id: Uuid) -> Result<Newsletter, anyhow::Error> {
-    let issue = sqlx::query_as!(
-        Newsletter,
+async fn fail_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    n_attempts: i16,
+    error: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
         r#"
-            SELECT title, html_content, text_content
+        INSERT INTO issue_delivery_failures (
+            newsletter_issue_id,
+            subscriber_email,
+            n_attempts,
+            error,
+            failed_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            n_attempts = EXCLUDED.n_attempts,
+            error = EXCLUDED.error,
+            failed_at = EXCLUDED.failed_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        n_attempts,
+        error
+    )
+    .execute(&mut *transaction)
+    .await?;
+    delete_task(transaction, task, DeliveryOutcome::Failed).await
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_issues(
+    pool: &PgPool,
+    tasks: &[DeliveryTask],
+) -> Result<HashMap<Uuid, Newsletter>, anyhow::Error> {
+    let issue_ids: Vec<Uuid> = tasks.iter().map(|t| t.newsletter_issue_id).collect();
+    let rows = sqlx::query!(
+        r#"
+            SELECT newsletter_issue_id, title, html_content, text_content, markdown_content
             FROM newsletter_issues
             WHERE
-                newsletter_issue_id = $1
+                newsletter_issue_id = ANY($1)
         "#,
-        issue_id
+        &issue_ids[..]
     )
-    .fetch_one(pool)
+    .fetch_all(pool)
     .await?;
-    Ok(issue)
+    let issues = rows
+        .into_iter()
+        .map(|r| {
+            let issue = Newsletter {
+                title: r.title,
+                html_content: r.html_content,
+                text_content: r.text_content,
+                markdown_content: r.markdown_content,
+            };
+            (r.newsletter_issue_id, issue)
+        })
+        .collect();
+    Ok(issues)
 }
 
-fn sleep(seconds: u64) -> Sleep {
-    tokio::time::sleep(Duration::from_secs(seconds))
+// Exponential backoff capped at `max_backoff`, with "equal jitter": we always wait at least half
+// of the computed delay and pick the rest at random so retries from the same outage spread out.
+pub(crate) fn retry_delay(worker_settings: &WorkerSettings, n_attempts: i16) -> Duration {
+    let exponent = (n_attempts.max(1) - 1).min(31) as u32;
+    let delay = worker_settings
+        .initial_backoff()
+        .saturating_mul(2u32.saturating_pow(exponent))
+        .min(worker_settings.max_backoff());
+    let half = delay / 2;
+    half + rand::thread_rng().gen_range(Duration::ZERO..=half)
+}
+
+// Only confirmed subscribers are returned: whoever unsubscribed after the issue was queued must
+// not receive it.
+#[tracing::instrument(skip_all)]
+async fn get_subscribers(
+    pool: &PgPool,
+    tasks: &[DeliveryTask],
+) -> Result<HashMap<String, Subscriber>, anyhow::Error> {
+    let emails: Vec<String> = tasks.iter().map(|t| t.subscriber_email.clone()).collect();
+    let rows = sqlx::query!(
+        r#"
+            SELECT id, email, name
+            FROM subscriptions
+            WHERE email = ANY($1) AND status = 'confirmed'
+        "#,
+        &emails[..]
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|r| {
+            let subscriber = Subscriber {
+                id: r.id,
+                name: r.name,
+            };
+            (r.email, subscriber)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::configuration::WorkerSettings;
+    use crate::issue_delivery_worker::{add_click_tracking, retry_delay};
+    use std::time::Duration;
+
+    fn worker_settings() -> WorkerSettings {
+        WorkerSettings {
+            max_attempts: 10,
+            initial_backoff_milliseconds: 1000,
+            max_backoff_milliseconds: 60_000,
+            batch_size: 100,
+            pool_size: 1,
+            poll_interval_milliseconds: 10_000,
+            error_backoff_milliseconds: 1000,
+            track_opens: true,
+            track_clicks: true,
+        }
+    }
+
+    #[test]
+    fn retry_delay_grows_exponentially_with_each_attempt() {
+        let settings = worker_settings();
+        for (n_attempts, expected) in [(1, 1000), (2, 2000), (3, 4000), (4, 8000)] {
+            let delay = retry_delay(&settings, n_attempts);
+            let expected = Duration::from_millis(expected);
+            assert!(delay >= expected / 2 && delay <= expected, "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn retry_delay_is_capped_at_max_backoff() {
+        let settings = worker_settings();
+        let delay = retry_delay(&settings, i16::MAX);
+        assert!(delay <= settings.max_backoff());
+        assert!(delay >= settings.max_backoff() / 2);
+    }
+
+    #[test]
+    fn web_links_are_routed_through_click_tracking() {
+        let html = r#"<p><a href="https://example.com/?a=1&amp;b=2" title="x">one</a> <a href="mailto:me@example.com">two</a> <a href="https://example.com/skip">three</a></p>"#;
+        let tracked = add_click_tracking(html, |url| {
+            (url.path() != "/skip").then(|| {
+                let mut tracked = reqwest::Url::parse("https://t.example.com/t").unwrap();
+                tracked.query_pairs_mut().append_pair("to", url.as_str());
+                tracked
+            })
+        });
+        assert_eq!(
+            tracked,
+            r#"<p><a href="https://t.example.com/t?to=https%3A%2F%2Fexample.com%2F%3Fa%3D1%26b%3D2" title="x">one</a> <a href="mailto:me@example.com">two</a> <a href="https://example.com/skip">three</a></p>"#
+        );
+    }
 }
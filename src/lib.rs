@@ -1,9 +1,15 @@
 pub mod authentication;
 pub mod configuration;
+pub mod consent;
 pub mod domain;
 pub mod email_client;
+pub mod email_outbox_worker;
+pub mod html_sanitizer;
+pub mod html_to_text;
 pub mod idempotency;
 pub mod issue_delivery_worker;
+pub mod link_signer;
+pub mod markdown;
 pub mod routes;
 pub mod session_state;
 pub mod startup;
@@ -0,0 +1,255 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<sha2::Sha256>;
+
+// Builds links that are embedded in outgoing emails and authenticate the recipient without a
+// session. Every token is bound to a purpose so a link minted for one action can not be replayed
+// against another endpoint.
+#[derive(Clone)]
+pub struct LinkSigner {
+    base_url: Url,
+    hmac_secret: Secret<String>,
+}
+
+impl LinkSigner {
+    const UNSUBSCRIBE: &'static str = "unsubscribe";
+    const OPEN: &'static str = "open";
+    const CLICK: &'static str = "click";
+    const PREFERENCES: &'static str = "preferences";
+    const EMAIL_CHANGE: &'static str = "email_change";
+
+    pub fn new(base_url: Url, hmac_secret: Secret<String>) -> Self {
+        Self {
+            base_url,
+            hmac_secret,
+        }
+    }
+
+    pub fn unsubscribe_url(&self, subscriber_id: Uuid) -> Url {
+        let token = self.sign(Self::UNSUBSCRIBE, &subscriber_id.to_string());
+        self.url("/subscriptions/unsubscribe", &token)
+    }
+
+    pub fn verify_unsubscribe_token(&self, token: &str) -> Result<Uuid, anyhow::Error> {
+        let subscriber_id = self.verify(Self::UNSUBSCRIBE, token)?;
+        Ok(Uuid::parse_str(&subscriber_id)?)
+    }
+
+    pub fn preferences_url(&self, subscriber_id: Uuid) -> Url {
+        let token = self.sign(Self::PREFERENCES, &subscriber_id.to_string());
+        self.url("/subscriptions/preferences", &token)
+    }
+
+    pub fn verify_preferences_token(&self, token: &str) -> Result<Uuid, anyhow::Error> {
+        let subscriber_id = self.verify(Self::PREFERENCES, token)?;
+        Ok(Uuid::parse_str(&subscriber_id)?)
+    }
+
+    // Sent to the new address of a subscriber, following it proves they own that address
+    pub fn email_change_url(&self, subscriber_id: Uuid, new_email: &str) -> Url {
+        let payload = format!("{}.{}", subscriber_id, URL_SAFE_NO_PAD.encode(new_email));
+        let token = self.sign(Self::EMAIL_CHANGE, &payload);
+        self.url("/subscriptions/preferences/email", &token)
+    }
+
+    pub fn verify_email_change_token(&self, token: &str) -> Result<(Uuid, String), anyhow::Error> {
+        let payload = self.verify(Self::EMAIL_CHANGE, token)?;
+        let (subscriber_id, new_email) = payload
+            .split_once('.')
+            .ok_or_else(|| anyhow::anyhow!("The token is malformed."))?;
+        Ok((
+            Uuid::parse_str(subscriber_id)?,
+            String::from_utf8(URL_SAFE_NO_PAD.decode(new_email)?)?,
+        ))
+    }
+
+    // Tracking tokens are part of the path: `/t/{token}` redirects to the destination of a
+    // click and `/t/{token}/open.gif` serves the open tracking pixel.
+    pub fn open_tracking_url(&self, newsletter_issue_id: Uuid, subscriber_id: Uuid) -> Url {
+        let payload = format!("{}.{}", newsletter_issue_id, subscriber_id);
+        let token = self.sign(Self::OPEN, &payload);
+        self.join(&format!("/t/{}/open.gif", token))
+    }
+
+    pub fn verify_open_token(&self, token: &str) -> Result<(Uuid, Uuid), anyhow::Error> {
+        let payload = self.verify(Self::OPEN, token)?;
+        let (newsletter_issue_id, subscriber_id) = payload
+            .split_once('.')
+            .ok_or_else(|| anyhow::anyhow!("The token is malformed."))?;
+        Ok((
+            Uuid::parse_str(newsletter_issue_id)?,
+            Uuid::parse_str(subscriber_id)?,
+        ))
+    }
+
+    pub fn click_tracking_url(
+        &self,
+        newsletter_issue_id: Uuid,
+        subscriber_id: Uuid,
+        destination: &Url,
+    ) -> Url {
+        let payload = format!(
+            "{}.{}.{}",
+            newsletter_issue_id,
+            subscriber_id,
+            URL_SAFE_NO_PAD.encode(destination.as_str())
+        );
+        let token = self.sign(Self::CLICK, &payload);
+        self.join(&format!("/t/{}", token))
+    }
+
+    pub fn verify_click_token(&self, token: &str) -> Result<(Uuid, Uuid, Url), anyhow::Error> {
+        let payload = self.verify(Self::CLICK, token)?;
+        let mut parts = payload.splitn(3, '.');
+        let mut next_part = || {
+            parts
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("The token is malformed."))
+        };
+        let newsletter_issue_id = Uuid::parse_str(next_part()?)?;
+        let subscriber_id = Uuid::parse_str(next_part()?)?;
+        let destination = String::from_utf8(URL_SAFE_NO_PAD.decode(next_part()?)?)?;
+        Ok((
+            newsletter_issue_id,
+            subscriber_id,
+            Url::parse(&destination)?,
+        ))
+    }
+
+    fn join(&self, path: &str) -> Url {
+        self.base_url
+            .join(path)
+            .unwrap_or_else(|_| panic!("Invalid signed link path {}", path))
+    }
+
+    fn url(&self, path: &str, token: &str) -> Url {
+        let mut url = self.join(path);
+        url.query_pairs_mut().append_pair("token", token);
+        url
+    }
+
+    fn mac(&self, purpose: &str, payload: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(self.hmac_secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(purpose.as_bytes());
+        mac.update(b":");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    fn sign(&self, purpose: &str, payload: &str) -> String {
+        let tag = self.mac(purpose, payload).finalize().into_bytes();
+        format!("{}.{}", payload, hex::encode(tag))
+    }
+
+    fn verify(&self, purpose: &str, token: &str) -> Result<String, anyhow::Error> {
+        let (payload, tag) = token
+            .rsplit_once('.')
+            .ok_or_else(|| anyhow::anyhow!("The token is malformed."))?;
+        let tag = hex::decode(tag)?;
+        self.mac(purpose, payload).verify_slice(&tag)?;
+        Ok(payload.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::link_signer::LinkSigner;
+    use claims::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn link_signer(secret: &str) -> LinkSigner {
+        LinkSigner::new(
+            reqwest::Url::parse("http://127.0.0.1").unwrap(),
+            Secret::new(secret.into()),
+        )
+    }
+
+    fn token(url: &reqwest::Url) -> String {
+        url.query_pairs()
+            .find(|(key, _)| key == "token")
+            .unwrap()
+            .1
+            .into_owned()
+    }
+
+    #[test]
+    fn a_signed_unsubscribe_link_is_verified() {
+        let signer = link_signer("secret");
+        let subscriber_id = Uuid::new_v4();
+        let url = signer.unsubscribe_url(subscriber_id);
+        assert_eq!(url.path(), "/subscriptions/unsubscribe");
+        assert_ok_eq!(signer.verify_unsubscribe_token(&token(&url)), subscriber_id);
+    }
+
+    #[test]
+    fn a_tampered_token_is_rejected() {
+        let signer = link_signer("secret");
+        let url = signer.unsubscribe_url(Uuid::new_v4());
+        let (_, tag) = token(&url)
+            .rsplit_once('.')
+            .map(|(p, t)| (p.to_owned(), t.to_owned()))
+            .unwrap();
+        let tampered = format!("{}.{}", Uuid::new_v4(), tag);
+        assert_err!(signer.verify_unsubscribe_token(&tampered));
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let url = link_signer("secret").unsubscribe_url(Uuid::new_v4());
+        assert_err!(link_signer("another-secret").verify_unsubscribe_token(&token(&url)));
+    }
+
+    #[test]
+    fn a_signed_click_tracking_link_is_verified() {
+        let signer = link_signer("secret");
+        let (issue_id, subscriber_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let destination = reqwest::Url::parse("https://example.com/a.b?c=d&e=f#g").unwrap();
+        let url = signer.click_tracking_url(issue_id, subscriber_id, &destination);
+        let token = url.path().strip_prefix("/t/").unwrap();
+        assert_ok_eq!(
+            signer.verify_click_token(token),
+            (issue_id, subscriber_id, destination)
+        );
+    }
+
+    #[test]
+    fn a_signed_email_change_link_is_verified() {
+        let signer = link_signer("secret");
+        let subscriber_id = Uuid::new_v4();
+        let url = signer.email_change_url(subscriber_id, "new.address@example.com");
+        assert_eq!(url.path(), "/subscriptions/preferences/email");
+        assert_ok_eq!(
+            signer.verify_email_change_token(&token(&url)),
+            (subscriber_id, "new.address@example.com".to_string())
+        );
+        assert_err!(signer.verify_preferences_token(&token(&url)));
+    }
+
+    #[test]
+    fn tokens_can_not_be_used_for_another_purpose() {
+        let signer = link_signer("secret");
+        let url = signer.open_tracking_url(Uuid::new_v4(), Uuid::new_v4());
+        let token = url
+            .path()
+            .strip_prefix("/t/")
+            .and_then(|p| p.strip_suffix("/open.gif"))
+            .unwrap();
+        assert!(signer.verify_open_token(token).is_ok());
+        assert_err!(signer.verify_click_token(token));
+        assert_err!(signer.verify_unsubscribe_token(token));
+    }
+
+    #[test]
+    fn a_malformed_token_is_rejected() {
+        let signer = link_signer("secret");
+        assert_err!(signer.verify_unsubscribe_token("not-a-token"));
+        assert_err!(signer.verify_unsubscribe_token("a.not-hex"));
+    }
+}
@@ -1,12 +1,39 @@
+use actix_web::dev::ServerHandle;
+use clap::{Parser, Subcommand};
 use std::fmt::{Debug, Display};
 use tokio::task::JoinError;
-use zero2prod::configuration::get_configuration;
+use tokio_util::sync::CancellationToken;
+use zero2prod::configuration::{get_configuration, Settings};
 use zero2prod::issue_delivery_worker::run_worker_until_stopped;
-use zero2prod::startup::Application;
+use zero2prod::startup::{get_connection_pool, Application};
 use zero2prod::telemetry::{get_subscriber, subscriber_init};
 
+const API: &str = "API";
+const WORKER: &str = "Newsletter delivery background worker";
+
+#[derive(Parser)]
+#[command(about = "zero2prod newsletter service")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serve the HTTP API only
+    Serve,
+    /// Run the newsletter delivery worker only
+    Worker,
+    /// Run both the HTTP API and the delivery worker in a single process (default)
+    All,
+    /// Apply the pending database migrations and exit
+    Migrate,
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
     // Setting up log env
     let subscriber = get_subscriber("zero2prod".into(), "trace".into(), std::io::stdout);
     subscriber_init(subscriber);
@@ -14,26 +41,96 @@ async fn main() -> anyhow::Result<()> {
     // Panic if for some resson we can't read the configuration file
     let configuration = get_configuration().expect("Failed to read configuration.");
 
+    match cli.command.unwrap_or(Command::All) {
+        Command::Serve => serve(configuration).await,
+        Command::Worker => worker(configuration).await,
+        Command::All => all(configuration).await,
+        Command::Migrate => migrate(configuration).await,
+    }
+}
+
+async fn serve(configuration: Settings) -> anyhow::Result<()> {
+    let shutdown = CancellationToken::new();
+    let application = Application::build(configuration).await?;
+    tokio::spawn(cancel_on_shutdown_signal(shutdown.clone()));
+    tokio::spawn(stop_server_on_shutdown(shutdown, application.handle()));
+    report_exit(API, tokio::spawn(application.run_until_stopped()).await);
+    Ok(())
+}
+
+async fn worker(configuration: Settings) -> anyhow::Result<()> {
+    let shutdown = CancellationToken::new();
+    tokio::spawn(cancel_on_shutdown_signal(shutdown.clone()));
+    let worker_task = tokio::spawn(run_worker_until_stopped(configuration, shutdown));
+    report_exit(WORKER, worker_task.await);
+    Ok(())
+}
+
+async fn all(configuration: Settings) -> anyhow::Result<()> {
+    let shutdown = CancellationToken::new();
     let application = Application::build(configuration.clone()).await?;
+    tokio::spawn(cancel_on_shutdown_signal(shutdown.clone()));
+    tokio::spawn(stop_server_on_shutdown(
+        shutdown.clone(),
+        application.handle(),
+    ));
     let application_task = tokio::spawn(application.run_until_stopped());
-    let worker_task = tokio::spawn(run_worker_until_stopped(configuration));
+    let worker_task = tokio::spawn(run_worker_until_stopped(configuration, shutdown.clone()));
 
-    // wait on multiple concurrent futrues
-    // pitfal with select
-    //
-    // By running all async expressions on the current task, the expressions are able to run concurrently but
-    // not in parallel. This means all expressions are run on the same thread and if one branch blocks the
-    // thread, all other expressions will be unable to continue. If parallelism is required, spawn each async
-    // expression using tokio::spawn and pass the join handle to select!.
+    // Whichever task exits first -- because of a signal or a failure -- brings the other one down
+    // gracefully and we wait for both of them before exiting.
+    tokio::join!(
+        async {
+            report_exit(API, application_task.await);
+            shutdown.cancel();
+        },
+        async {
+            report_exit(WORKER, worker_task.await);
+            shutdown.cancel();
+        },
+    );
 
-    tokio::select! {
-        o = application_task => report_exit("API", o),
-        o = worker_task => report_exit("Newsletter delivery background worker", o),
-    };
+    Ok(())
+}
 
+async fn migrate(configuration: Settings) -> anyhow::Result<()> {
+    let connection_pool = get_connection_pool(&configuration.database);
+    sqlx::migrate!("./migrations").run(&connection_pool).await?;
+    tracing::info!("Database migrations have been applied");
     Ok(())
 }
 
+async fn cancel_on_shutdown_signal(shutdown: CancellationToken) {
+    tokio::select! {
+        _ = shutdown_signal() => {
+            tracing::info!("Shutdown signal received -- Finishing in-flight work.")
+        }
+        _ = shutdown.cancelled() => {}
+    }
+    shutdown.cancel();
+}
+
+async fn stop_server_on_shutdown(shutdown: CancellationToken, server_handle: ServerHandle) {
+    shutdown.cancelled().await;
+    // Stop accepting connections and wait for in-flight requests to complete
+    server_handle.stop(true).await;
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = tokio::signal::ctrl_c();
+    #[cfg(unix)]
+    {
+        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install the SIGTERM handler.");
+        tokio::select! {
+            _ = ctrl_c => {}
+            _ = sigterm.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = ctrl_c.await;
+}
+
 fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
     match outcome {
         Ok(Ok(())) => tracing::info!("{} has exited", task_name),
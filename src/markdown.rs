@@ -0,0 +1,325 @@
+// Renders the Markdown subset newsletter authors need: headings, paragraphs, emphasis, inline
+// code, fenced code blocks, links, images, block quotes, lists and horizontal rules.
+// The output is safe to send as is: raw HTML in the source is escaped rather than passed through
+// and only links to web pages, email addresses or merge fields are kept.
+use crate::utils::escape_html;
+
+pub fn markdown_to_html(markdown: &str) -> String {
+    let lines: Vec<&str> = markdown.lines().collect();
+    let mut html = String::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim();
+        let block_start = trimmed.is_empty()
+            || trimmed.starts_with("```")
+            || heading(trimmed).is_some()
+            || is_horizontal_rule(trimmed)
+            || trimmed.starts_with('>')
+            || list_item(line).is_some();
+        if !block_start {
+            paragraph.push(trimmed);
+            // Two trailing spaces force a line break, as in CommonMark
+            if line.ends_with("  ") {
+                paragraph.push("\n");
+            }
+            i += 1;
+            continue;
+        }
+        flush_paragraph(&mut html, &mut paragraph);
+        if trimmed.is_empty() {
+            i += 1;
+        } else if trimmed.starts_with("```") {
+            // The language of the fence is ignored, there is no syntax highlighting
+            let end = lines[i + 1..]
+                .iter()
+                .position(|l| l.trim().starts_with("```"))
+                .map_or(lines.len(), |end| i + 1 + end);
+            let code = lines[i + 1..end].join("\n");
+            html.push_str(&format!("<pre><code>{}</code></pre>\n", escape_html(&code)));
+            i = end + 1;
+        } else if let Some((level, text)) = heading(trimmed) {
+            html.push_str(&format!("<h{0}>{1}</h{0}>\n", level, render_inline(text)));
+            i += 1;
+        } else if is_horizontal_rule(trimmed) {
+            html.push_str("<hr>\n");
+            i += 1;
+        } else if trimmed.starts_with('>') {
+            let mut quoted = Vec::new();
+            while i < lines.len() && lines[i].trim().starts_with('>') {
+                let line = lines[i].trim().trim_start_matches('>');
+                quoted.push(line.strip_prefix(' ').unwrap_or(line));
+                i += 1;
+            }
+            html.push_str(&format!(
+                "<blockquote>\n{}</blockquote>\n",
+                markdown_to_html(&quoted.join("\n"))
+            ));
+        } else {
+            i = render_list(&lines, i, &mut html);
+        }
+    }
+    flush_paragraph(&mut html, &mut paragraph);
+    html
+}
+
+fn flush_paragraph(html: &mut String, paragraph: &mut Vec<&str>) {
+    if paragraph.is_empty() {
+        return;
+    }
+    let mut content = String::new();
+    for line in paragraph.iter() {
+        if *line == "\n" {
+            content.push_str("<br>");
+        } else {
+            if !content.is_empty() && !content.ends_with("<br>") {
+                content.push(' ');
+            }
+            content.push_str(&render_inline(line));
+        }
+    }
+    html.push_str(&format!("<p>{}</p>\n", content));
+    paragraph.clear();
+}
+
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let text = &line[level..];
+    if text.is_empty() || text.starts_with(' ') {
+        Some((level, text.trim().trim_end_matches('#').trim_end()))
+    } else {
+        None
+    }
+}
+
+fn is_horizontal_rule(line: &str) -> bool {
+    let marks: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    marks.len() >= 3
+        && ["-", "*", "_"]
+            .iter()
+            .any(|mark| marks.chars().all(|c| c.to_string() == *mark))
+}
+
+struct ListItem<'a> {
+    indent: usize,
+    ordered: bool,
+    // Width of the indentation and marker, continuation lines are indented by at least as much
+    content_offset: usize,
+    text: &'a str,
+}
+
+fn list_item(line: &str) -> Option<ListItem<'_>> {
+    let indent = line.len() - line.trim_start().len();
+    let rest = &line[indent..];
+    let (ordered, marker_len) = if ["- ", "* ", "+ "].iter().any(|m| rest.starts_with(m)) {
+        (false, 2)
+    } else {
+        let digits = rest.chars().take_while(char::is_ascii_digit).count();
+        if digits == 0 || digits > 9 || !rest[digits..].starts_with(". ") {
+            return None;
+        }
+        (true, digits + 2)
+    };
+    Some(ListItem {
+        indent,
+        ordered,
+        content_offset: indent + marker_len,
+        text: rest[marker_len..].trim(),
+    })
+}
+
+// Renders the list starting at `lines[start]` and returns the index of the first line after it.
+// Lines indented under an item (such as a nested list) are rendered as part of that item.
+fn render_list(lines: &[&str], start: usize, html: &mut String) -> usize {
+    let first = list_item(lines[start]).expect("A list starts with a list item");
+    let tag = if first.ordered { "ol" } else { "ul" };
+    html.push_str(&format!("<{}>\n", tag));
+    let mut i = start;
+    while i < lines.len() {
+        let item = match list_item(lines[i]) {
+            Some(item) if item.indent == first.indent && item.ordered == first.ordered => item,
+            _ => break,
+        };
+        i += 1;
+        let mut nested = Vec::new();
+        while i < lines.len() {
+            let line = lines[i];
+            let indent = line.len() - line.trim_start().len();
+            if line.trim().is_empty() || indent < item.content_offset.min(first.indent + 2) {
+                break;
+            }
+            // Indentation is measured in bytes, wide whitespace such as U+3000 is not cut in half
+            let strip = line
+                .char_indices()
+                .map(|(offset, _)| offset)
+                .take_while(|offset| *offset <= item.content_offset.min(indent))
+                .last()
+                .unwrap_or(0);
+            nested.push(&line[strip..]);
+            i += 1;
+        }
+        html.push_str(&format!("<li>{}", render_inline(item.text)));
+        if !nested.is_empty() {
+            html.push('\n');
+            html.push_str(&markdown_to_html(&nested.join("\n")));
+        }
+        html.push_str("</li>\n");
+    }
+    html.push_str(&format!("</{}>\n", tag));
+    i
+}
+
+fn render_inline(text: &str) -> String {
+    let mut html = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        // `\*` and friends are taken literally
+        let escaped = rest
+            .strip_prefix('\\')
+            .and_then(|r| r.chars().next())
+            .filter(char::is_ascii_punctuation);
+        if let Some(escaped) = escaped {
+            html.push_str(&escape_html(&escaped.to_string()));
+            rest = &rest[2..];
+        } else if let Some((rendered, remainder)) = render_span(rest) {
+            html.push_str(&rendered);
+            rest = remainder;
+        } else {
+            html.push_str(&escape_html(&c.to_string()));
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    html
+}
+
+// Tries to render the span starting at the beginning of `text`
+fn render_span(text: &str) -> Option<(String, &str)> {
+    if text.starts_with("{{") {
+        // Merge fields are filled in at delivery time, their names must be left untouched
+        let end = text.find("}}")? + 2;
+        return Some((escape_html(&text[..end]), &text[end..]));
+    }
+    if let Some(code) = text.strip_prefix('`') {
+        let end = code.find('`')?;
+        let rendered = format!("<code>{}</code>", escape_html(&code[..end]));
+        return Some((rendered, &code[end + 1..]));
+    }
+    for (delimiter, tag) in [("**", "strong"), ("__", "strong"), ("*", "em")] {
+        if let Some(inner) = text.strip_prefix(delimiter) {
+            let end = inner.find(delimiter)?;
+            if end == 0 || inner.starts_with(' ') {
+                return None;
+            }
+            let rendered = format!("<{0}>{1}</{0}>", tag, render_inline(&inner[..end]));
+            return Some((rendered, &inner[end + delimiter.len()..]));
+        }
+    }
+    if let Some(image) = text.strip_prefix("![") {
+        let (alt, url, rest) = link_parts(image)?;
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return None;
+        }
+        let rendered = format!(
+            r#"<img src="{}" alt="{}">"#,
+            escape_html(url),
+            escape_html(alt)
+        );
+        return Some((rendered, rest));
+    }
+    if let Some(link) = text.strip_prefix('[') {
+        let (label, url, rest) = link_parts(link)?;
+        let label = render_inline(label);
+        let rendered = if is_safe_url(url) {
+            format!(r#"<a href="{}">{}</a>"#, escape_html(url), label)
+        } else {
+            label
+        };
+        return Some((rendered, rest));
+    }
+    None
+}
+
+// Splits `label](url) rest` into its parts
+fn link_parts(text: &str) -> Option<(&str, &str, &str)> {
+    let label_end = text.find("](")?;
+    let after_label = &text[label_end + 2..];
+    let url_end = after_label.find(')')?;
+    Some((
+        &text[..label_end],
+        after_label[..url_end].trim(),
+        &after_label[url_end + 1..],
+    ))
+}
+
+// Leaves out `javascript:` and other schemes with no business in a newsletter
+fn is_safe_url(url: &str) -> bool {
+    let lowercase = url.to_ascii_lowercase();
+    ["https://", "http://", "mailto:", "/", "#", "{{"]
+        .iter()
+        .any(|prefix| lowercase.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markdown::markdown_to_html;
+
+    #[test]
+    fn paragraphs_and_headings_are_rendered() {
+        assert_eq!(
+            markdown_to_html("# Title\n\nSome *emphasis* and **strong**\ntext.\n\n## Next"),
+            "<h1>Title</h1>\n<p>Some <em>emphasis</em> and <strong>strong</strong> text.</p>\n<h2>Next</h2>\n"
+        );
+    }
+
+    #[test]
+    fn raw_html_is_escaped() {
+        assert_eq!(
+            markdown_to_html("<script>alert('hi')</script>"),
+            "<p>&lt;script&gt;alert(&#x27;hi&#x27;)&lt;/script&gt;</p>\n"
+        );
+    }
+
+    #[test]
+    fn links_with_unsafe_schemes_are_dropped() {
+        assert_eq!(
+            markdown_to_html("[ok](https://example.com?a=1&b=2) [bad](javascript:void)"),
+            "<p><a href=\"https://example.com?a=1&amp;b=2\">ok</a> bad</p>\n"
+        );
+    }
+
+    #[test]
+    fn merge_fields_are_left_untouched() {
+        assert_eq!(
+            markdown_to_html("Hi {{ name }}, [leave]({{ unsubscribe_url }})"),
+            "<p>Hi {{ name }}, <a href=\"{{ unsubscribe_url }}\">leave</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn nested_lists_are_rendered() {
+        assert_eq!(
+            markdown_to_html("- one\n- two\n  1. a\n  2. b\n- three"),
+            "<ul>\n<li>one</li>\n<li>two\n<ol>\n<li>a</li>\n<li>b</li>\n</ol>\n</li>\n<li>three</li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn non_ascii_indentation_is_not_split() {
+        assert_eq!(
+            markdown_to_html("- a\n\u{3000}b"),
+            "<ul>\n<li>a\n<p>b</p>\n</li>\n</ul>\n"
+        );
+    }
+
+    #[test]
+    fn code_quotes_and_rules_are_rendered() {
+        assert_eq!(
+            markdown_to_html("```rust\nlet x = 1 < 2;\n```\n> quoted `code`\n\n---"),
+            "<pre><code>let x = 1 &lt; 2;</code></pre>\n<blockquote>\n<p>quoted <code>code</code></p>\n</blockquote>\n<hr>\n"
+        );
+    }
+}
@@ -1,9 +1,10 @@
 use crate::authentication::UserId;
-use crate::utils::e500;
+use crate::utils::{e500, escape_html};
 use actix_web::http::header::ContentType;
 use actix_web::{get, web, HttpResponse};
 use anyhow::Context;
 use sqlx::PgPool;
+use std::fmt::Write;
 use uuid::Uuid;
 
 #[get("/dashboard")]
@@ -14,6 +15,26 @@ pub async fn admin_dashboard(
     let user_id = user_id.into_inner();
 
     let username = get_username(*user_id, &pool).await.map_err(e500)?;
+    let mut engagement_html = String::new();
+    for issue in get_issue_engagement(&pool).await.map_err(e500)? {
+        writeln!(
+            engagement_html,
+            r#"<tr>
+                <td><a href="/admin/newsletters/{}">{}</a></td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>"#,
+            issue.newsletter_issue_id,
+            escape_html(&issue.title),
+            issue.n_delivered,
+            issue.n_opened,
+            issue.n_clicked,
+            issue.n_clicks,
+        )
+        .unwrap();
+    }
 
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
@@ -26,11 +47,26 @@ pub async fn admin_dashboard(
             </head>
             <body>
                 <p>Welcome {username}!</p>
+                <h2>Recent issues</h2>
+                <table>
+                    <tr>
+                        <th>Title</th>
+                        <th>Sent</th>
+                        <th>Opened</th>
+                        <th>Clicked</th>
+                        <th>Clicks</th>
+                    </tr>
+                    {engagement_html}
+                </table>
             </body>
             <p>Available actions:</p>
             <ol>
                 <li><a href="/admin/newsletters">Publish a newsletter</a></li>
+                <li><a href="/admin/newsletters/drafts">Newsletter drafts</a></li>
+                <li><a href="/admin/lists">Subscriber lists</a></li>
+                <li><a href="/admin/subscribers">Subscribers</a></li>
                 <li><a href="/admin/password">Change password</a></li>
+                <li><a href="/admin/email">Change email address</a></li>
                 <li>
                     <form name="logoutForm" action="/admin/logout" method="post">
                         <input type="submit" value="Logout">
@@ -57,3 +93,42 @@ pub async fn get_username(user_id: Uuid, pool: &PgPool) -> Result<String, anyhow
 
     Ok(row.username)
 }
+
+struct IssueEngagement {
+    newsletter_issue_id: Uuid,
+    title: String,
+    n_delivered: i32,
+    // Opened and clicked count subscribers, clicks counts every click
+    n_opened: i64,
+    n_clicked: i64,
+    n_clicks: i64,
+}
+
+#[tracing::instrument(name = "Get issue engagement", skip(pool))]
+async fn get_issue_engagement(pool: &PgPool) -> Result<Vec<IssueEngagement>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        IssueEngagement,
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.title,
+            i.n_delivered,
+            COUNT(DISTINCT e.subscriber_id) FILTER (WHERE e.event_type = 'open') AS "n_opened!",
+            COUNT(DISTINCT e.subscriber_id) FILTER (WHERE e.event_type = 'click') AS "n_clicked!",
+            COUNT(e.newsletter_event_id) FILTER (WHERE e.event_type = 'click') AS "n_clicks!"
+        FROM newsletter_issues i
+        LEFT JOIN newsletter_events e ON e.newsletter_issue_id = i.newsletter_issue_id
+        WHERE
+            i.published_at IS NOT NULL AND
+            (i.scheduled_for IS NULL OR i.scheduled_for <= now())
+        GROUP BY i.newsletter_issue_id
+        ORDER BY GREATEST(i.published_at, i.scheduled_for) DESC
+        LIMIT 10
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve the engagement of recent issues.")?;
+
+    Ok(issues)
+}
@@ -0,0 +1,72 @@
+use crate::authentication::UserId;
+use crate::utils::{e500, escape_html};
+use actix_web::http::header::ContentType;
+use actix_web::{get, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+#[get("/email")]
+pub async fn change_email_form(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut error_html = String::new();
+    for message in flash_messages.iter() {
+        writeln!(error_html, "<p><i>{}</i></p>", message.content()).unwrap();
+    }
+    let email = get_user_email(*user_id.into_inner(), &pool)
+        .await
+        .map_err(e500)?
+        .map(|email| escape_html(&email))
+        .unwrap_or_default();
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Email address</title>
+            </head>
+            <body>
+                {error_html}
+                <p>Test sends of newsletter drafts are delivered to this address.</p>
+                <form action="/admin/email" method="post">
+                    <label>Email address
+                        <input
+                        type="email"
+                        placeholder="Enter your email address"
+                        name="email"
+                        value="{email}"
+                        >
+                    </label>
+                    <br>
+                    <button type="submit">Save email address</button>
+                </form>
+                <p><a href="/admin/dashboard">&lt;- Back</a></p>
+            </body>
+            </html>"#
+        )))
+}
+
+#[tracing::instrument(name = "Get user email", skip(pool))]
+pub async fn get_user_email(user_id: Uuid, pool: &PgPool) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT email
+        FROM users
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform a query to retrieve a user email.")?;
+
+    Ok(row.email)
+}
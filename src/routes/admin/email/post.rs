@@ -0,0 +1,48 @@
+use crate::authentication::UserId;
+use crate::domain::SubscriberEmail;
+use crate::utils::{e500, escape_html, see_other};
+use actix_web::{post, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    email: String,
+}
+
+#[tracing::instrument(name = "Change email", skip(form, pool))]
+#[post("/email")]
+pub async fn change_email(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = match SubscriberEmail::parse(form.email.clone()) {
+        Ok(email) => email,
+        Err(_) => {
+            // Flash messages are rendered as is, the submitted value must be escaped
+            FlashMessage::error(format!(
+                "{} is not a valid email address.",
+                escape_html(&form.email)
+            ))
+            .send();
+            return Ok(see_other("/admin/email"));
+        }
+    };
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET email = $1
+        WHERE user_id = $2
+        "#,
+        email.as_ref(),
+        *user_id.into_inner()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to change the user email.")
+    .map_err(e500)?;
+    FlashMessage::info("Your email address has been changed.").send();
+    Ok(see_other("/admin/email"))
+}
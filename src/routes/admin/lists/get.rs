@@ -0,0 +1,125 @@
+use crate::utils::{e500, escape_html};
+use actix_web::http::header::ContentType;
+use actix_web::{get, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+pub struct SubscriberList {
+    pub list_id: Uuid,
+    pub name: String,
+}
+
+#[get("/lists")]
+pub async fn subscriber_lists(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut message_html = String::new();
+    for message in flash_messages.iter() {
+        writeln!(message_html, "<p><i>{}</i></p>", message.content()).unwrap();
+    }
+    let mut lists_html = String::new();
+    for (name, n_confirmed) in count_list_members(&pool).await.map_err(e500)? {
+        writeln!(
+            lists_html,
+            r#"<tr>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>"#,
+            escape_html(&name),
+            n_confirmed
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Subscriber lists</title>
+            </head>
+            <body>
+                {message_html}
+                <h1>Subscriber lists</h1>
+                <p>Subscribers pick the lists they join when they subscribe.
+                An issue sent to lists only reaches their confirmed members.</p>
+                <table>
+                    <tr>
+                        <th>Name</th>
+                        <th>Confirmed members</th>
+                    </tr>
+                    {lists_html}
+                </table>
+                <form action="/admin/lists" method="post">
+                    <label>Name:
+                        <input type="text" placeholder="Enter the list name" name="name">
+                    </label>
+                    <button type="submit">Create list</button>
+                </form>
+                <p><a href="/admin/dashboard">&lt;- Back</a></p>
+            </body>
+            </html>"#,
+        )))
+}
+
+// One `list_id` checkbox per list, for forms that target or join lists
+pub fn list_checkboxes_html(lists: &[SubscriberList], checked: &[Uuid]) -> String {
+    let mut checkboxes_html = String::new();
+    for list in lists {
+        writeln!(
+            checkboxes_html,
+            r#"<label><input type="checkbox" name="list_id" value="{}"{}> {}</label>"#,
+            list.list_id,
+            if checked.contains(&list.list_id) {
+                " checked"
+            } else {
+                ""
+            },
+            escape_html(&list.name)
+        )
+        .unwrap();
+    }
+    checkboxes_html
+}
+
+#[tracing::instrument(name = "Get subscriber lists", skip(pool))]
+pub async fn get_subscriber_lists(pool: &PgPool) -> Result<Vec<SubscriberList>, anyhow::Error> {
+    let lists = sqlx::query_as!(
+        SubscriberList,
+        r#"
+        SELECT list_id, name
+        FROM lists
+        ORDER BY name
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve subscriber lists.")?;
+
+    Ok(lists)
+}
+
+#[tracing::instrument(name = "Count subscriber list members", skip(pool))]
+async fn count_list_members(pool: &PgPool) -> Result<Vec<(String, i64)>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT l.name, COUNT(s.id) as "n_confirmed!"
+        FROM lists l
+        LEFT JOIN subscriptions_lists sl ON sl.list_id = l.list_id
+        LEFT JOIN subscriptions s ON s.id = sl.subscriber_id AND s.status = 'confirmed'
+        GROUP BY l.list_id, l.name
+        ORDER BY l.name
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to count subscriber list members.")?;
+
+    Ok(rows.into_iter().map(|r| (r.name, r.n_confirmed)).collect())
+}
@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::{get_subscriber_lists, list_checkboxes_html, subscriber_lists, SubscriberList};
+pub use post::create_subscriber_list;
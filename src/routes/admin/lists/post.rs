@@ -0,0 +1,61 @@
+use crate::utils::{e500, escape_html, see_other};
+use actix_web::{post, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+const MAX_LIST_NAME_LENGTH: usize = 100;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    name: String,
+}
+
+#[tracing::instrument(name = "Create a subscriber list", skip(form, pool))]
+#[post("/lists")]
+pub async fn create_subscriber_list(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let name = form.name.trim();
+    if name.is_empty() {
+        FlashMessage::error("The list name cannot be empty.").send();
+        return Ok(see_other("/admin/lists"));
+    }
+    if name.chars().count() > MAX_LIST_NAME_LENGTH {
+        FlashMessage::error(format!(
+            "The list name cannot be longer than {} characters.",
+            MAX_LIST_NAME_LENGTH
+        ))
+        .send();
+        return Ok(see_other("/admin/lists"));
+    }
+    let n_inserted = sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, name, created_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        name,
+        Utc::now()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to create a subscriber list.")
+    .map_err(e500)?
+    .rows_affected();
+    // Flash messages are rendered as is, the submitted name must be escaped
+    if n_inserted == 0 {
+        FlashMessage::error(format!(
+            "There already is a list named {}.",
+            escape_html(name)
+        ))
+        .send();
+    } else {
+        FlashMessage::info(format!("The list {} has been created.", escape_html(name))).send();
+    }
+    Ok(see_other("/admin/lists"))
+}
@@ -1,9 +1,16 @@
 mod dashboard;
+mod email;
+mod lists;
+#[allow(hidden_glob_reexports)]
 mod logout;
 mod newsletters;
 mod password;
+mod subscribers;
 
 pub use dashboard::admin_dashboard;
+pub use email::*;
+pub use lists::*;
 pub use logout::*;
 pub use newsletters::*;
 pub use password::*;
+pub use subscribers::*;
@@ -0,0 +1,48 @@
+use crate::utils::{e500, see_other};
+use actix_web::{post, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    in_archive: bool,
+}
+
+#[tracing::instrument(name = "Toggle a newsletter issue in the archive", skip(form, pool))]
+#[post("/newsletters/{newsletter_issue_id}/archive")]
+pub async fn set_newsletter_issue_in_archive(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            in_archive = $2,
+            archive_updated_at = CASE WHEN in_archive = $2 THEN archive_updated_at ELSE now() END
+        WHERE newsletter_issue_id = $1 AND published_at IS NOT NULL
+        "#,
+        newsletter_issue_id,
+        form.in_archive
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update whether a newsletter issue is in the archive.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated == 0 {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    match form.in_archive {
+        true => FlashMessage::info("The issue is now listed in the public archive.").send(),
+        false => FlashMessage::info("The issue is now hidden from the public archive.").send(),
+    }
+    Ok(see_other(&format!(
+        "/admin/newsletters/{}",
+        newsletter_issue_id
+    )))
+}
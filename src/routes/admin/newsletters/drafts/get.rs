@@ -0,0 +1,242 @@
+use crate::issue_delivery_worker::Newsletter;
+use crate::routes::admin::get_subscriber_lists;
+use crate::routes::admin::newsletters::get::audience_html;
+use crate::utils::{e500, escape_html};
+use actix_web::{get, http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{IncomingFlashMessages, Level};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+#[get("/newsletters/drafts")]
+pub async fn newsletter_drafts(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut message_html = String::new();
+    for message in flash_messages
+        .iter()
+        .filter(|m| m.level() == Level::Error || m.level() == Level::Info)
+    {
+        writeln!(message_html, "<p><i>{}</i></p>", message.content()).unwrap();
+    }
+    let mut drafts_html = String::new();
+    for (draft_id, title) in list_drafts(&pool).await.map_err(e500)? {
+        writeln!(
+            drafts_html,
+            r#"<li><a href="/admin/newsletters/drafts/{}">{}</a></li>"#,
+            draft_id,
+            escape_html(&title)
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Newsletter drafts</title>
+            </head>
+            <body>
+                {message_html}
+                <h1>Drafts</h1>
+                <ul>
+                    {drafts_html}
+                </ul>
+                <p><a href="/admin/newsletters">Write a new issue</a></p>
+                <p><a href="/admin/dashboard">&lt;- Back</a></p>
+            </body>
+            </html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "Show newsletter draft", skip(flash_messages, pool))]
+#[get("/newsletters/drafts/{draft_id}")]
+pub async fn newsletter_draft(
+    draft_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    let draft = match get_draft(draft_id, &pool).await.map_err(e500)? {
+        Some(draft) => draft,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let mut message_html = String::new();
+    for message in flash_messages
+        .iter()
+        .filter(|m| m.level() == Level::Error || m.level() == Level::Info)
+    {
+        writeln!(message_html, "<p><i>{}</i></p>", message.content()).unwrap();
+    }
+    let content_fields_html = content_fields_html(&draft);
+    let idempotency_key = Uuid::new_v4();
+    let audience_html = audience_html(&get_subscriber_lists(&pool).await.map_err(e500)?);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Newsletter draft</title>
+            </head>
+            <body>
+                {message_html}
+                <p>Personalise the content with the {{{{ name }}}}, {{{{ email }}}} and {{{{ unsubscribe_url }}}} merge fields.</p>
+                <form action="/admin/newsletters/drafts/{draft_id}" method="post">
+                    {content_fields_html}
+                    <label>Schedule for (UTC, leave empty to send right away):<br>
+                        <input type="datetime-local" name="scheduled_for">
+                    </label>
+                    <br>
+                    {audience_html}
+                    <input hidden type="text" name="draft_id" value="{draft_id}">
+                    <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
+                    <button type="submit">Save draft</button>
+                    <button type="submit" formaction="/admin/newsletters">Publish</button>
+                </form>
+                <p><a href="/admin/newsletters/drafts/{draft_id}/preview">Preview</a></p>
+                <form action="/admin/newsletters/drafts/{draft_id}/test" method="post">
+                    <button type="submit">Send test to myself</button>
+                </form>
+                <p><a href="/admin/newsletters/drafts">&lt;- Back</a></p>
+            </body>
+            </html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "Preview newsletter draft", skip(pool))]
+#[get("/newsletters/drafts/{draft_id}/preview")]
+pub async fn preview_newsletter_draft(
+    draft_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    let draft = match get_draft(draft_id, &pool).await.map_err(e500)? {
+        Some(draft) => draft,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let title = escape_html(&draft.title);
+    let text_content = escape_html(&draft.text_content);
+    // The HTML body is rendered in a sandboxed frame so it can not run scripts in the admin area
+    let html_content = escape_html(&draft.html_content);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Preview: {title}</title>
+            </head>
+            <body>
+                <h1>{title}</h1>
+                <h2>HTML</h2>
+                <iframe sandbox srcdoc="{html_content}" width="100%" height="600"></iframe>
+                <h2>Plain text</h2>
+                <pre>{text_content}</pre>
+                <p><a href="/admin/newsletters/drafts/{draft_id}">&lt;- Back</a></p>
+            </body>
+            </html>"#,
+        )))
+}
+
+// The title and content inputs, shared by the draft editor and the editor of scheduled issues
+pub fn content_fields_html(newsletter: &Newsletter) -> String {
+    let title = escape_html(&newsletter.title);
+    let text_content = escape_html(&newsletter.text_content);
+    let html_content = escape_html(&newsletter.html_content);
+    let markdown_content = escape_html(newsletter.markdown_content.as_deref().unwrap_or_default());
+    let (html_checked, markdown_checked) = match newsletter.markdown_content {
+        Some(_) => ("", " checked"),
+        None => (" checked", ""),
+    };
+    format!(
+        r#"<label>Title:<br>
+                    <input
+                        type="text"
+                        placeholder="Enter the issue title"
+                        name="title"
+                        value="{title}"
+                    >
+                </label>
+                <br>
+                <fieldset>
+                    <legend>Format:</legend>
+                    <label><input type="radio" name="format" value="html"{html_checked}> HTML and plain text</label>
+                    <label><input type="radio" name="format" value="markdown"{markdown_checked}> Markdown</label>
+                </fieldset>
+                <label>Markdown content (the HTML and plain text content are rendered from it):<br>
+                    <textarea
+                        placeholder="Enter the content in Markdown"
+                        name="markdown_content"
+                        rows="20"
+                        cols="50"
+                    >{markdown_content}</textarea>
+                </label>
+                <br>
+                <label>Plain text content (leave empty to derive it from the HTML content):<br>
+                    <textarea
+                        placeholder="Enter the content in plain text"
+                        name="text_content"
+                        rows="20"
+                        cols="50"
+                    >{text_content}</textarea>
+                </label>
+                <br>
+                <label>HTML content:<br>
+                    <textarea
+                        placeholder="Enter the content in HTML format"
+                        name="html_content"
+                        rows="20"
+                        cols="50"
+                    >{html_content}</textarea>
+                </label>
+                <br>"#
+    )
+}
+
+#[tracing::instrument(name = "Get newsletter draft", skip(pool))]
+pub async fn get_draft(draft_id: Uuid, pool: &PgPool) -> Result<Option<Newsletter>, anyhow::Error> {
+    let draft = sqlx::query_as!(
+        Newsletter,
+        r#"
+        SELECT title, html_content, text_content, markdown_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL
+        "#,
+        draft_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve a newsletter draft.")?;
+
+    Ok(draft)
+}
+
+#[tracing::instrument(name = "List newsletter drafts", skip(pool))]
+async fn list_drafts(pool: &PgPool) -> Result<Vec<(Uuid, String)>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id, title
+        FROM newsletter_issues
+        WHERE published_at IS NULL
+        ORDER BY title
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve newsletter drafts.")?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| (r.newsletter_issue_id, r.title))
+        .collect())
+}
@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::{content_fields_html, newsletter_draft, newsletter_drafts, preview_newsletter_draft};
+pub use post::{save_newsletter_draft, send_newsletter_draft_test, update_newsletter_draft};
@@ -0,0 +1,197 @@
+use crate::authentication::UserId;
+use crate::domain::{MergeFields, NewsletterTemplate, SubscriberEmail};
+use crate::email_client::EmailSender;
+use crate::routes::admin::dashboard::get_username;
+use crate::routes::admin::email::get_user_email;
+use crate::routes::admin::newsletters::drafts::get::get_draft;
+use crate::routes::admin::newsletters::post::{render_markdown, ContentFormat};
+use crate::utils::{e500, escape_html, see_other};
+use actix_web::{post, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// Drafts may be incomplete, only the title is needed to find them again later.
+// The publish fields of the draft form (`idempotency_key`, `scheduled_for`, `list_id`, ...) are
+// ignored.
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    title: String,
+    #[serde(default)]
+    format: ContentFormat,
+    #[serde(default)]
+    html_content: String,
+    #[serde(default)]
+    text_content: String,
+    #[serde(default)]
+    markdown_content: String,
+}
+
+impl FormData {
+    // Drafts written in Markdown are rendered on save so they can be previewed and test sent
+    fn content(&self) -> (String, String, Option<String>) {
+        match self.format {
+            ContentFormat::Markdown => {
+                let (html_content, text_content) = render_markdown(&self.markdown_content);
+                (
+                    html_content,
+                    text_content,
+                    Some(self.markdown_content.clone()),
+                )
+            }
+            ContentFormat::Html => (self.html_content.clone(), self.text_content.clone(), None),
+        }
+    }
+}
+
+#[tracing::instrument(name = "Save newsletter draft", skip(form, pool))]
+#[post("/newsletters/drafts")]
+pub async fn save_newsletter_draft(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if form.title.trim().is_empty() {
+        FlashMessage::error("Title cannot be empty.").send();
+        return Ok(see_other("/admin/newsletters"));
+    }
+    let draft_id = Uuid::new_v4();
+    let (html_content, text_content, markdown_content) = form.content();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            html_content,
+            text_content,
+            markdown_content
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        draft_id,
+        form.title,
+        html_content,
+        text_content,
+        markdown_content
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store a newsletter draft.")
+    .map_err(e500)?;
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&format!(
+        "/admin/newsletters/drafts/{}",
+        draft_id
+    )))
+}
+
+#[tracing::instrument(name = "Update newsletter draft", skip(form, pool))]
+#[post("/newsletters/drafts/{draft_id}")]
+pub async fn update_newsletter_draft(
+    draft_id: web::Path<Uuid>,
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    let draft_page = format!("/admin/newsletters/drafts/{}", draft_id);
+    if form.title.trim().is_empty() {
+        FlashMessage::error("Title cannot be empty.").send();
+        return Ok(see_other(&draft_page));
+    }
+    let (html_content, text_content, markdown_content) = form.content();
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            title = $2,
+            html_content = $3,
+            text_content = $4,
+            markdown_content = $5
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL
+        "#,
+        draft_id,
+        form.title,
+        html_content,
+        text_content,
+        markdown_content
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update a newsletter draft.")
+    .map_err(e500)?
+    .rows_affected();
+    if n_updated == 0 {
+        FlashMessage::error("Only drafts can be edited.").send();
+        return Ok(see_other("/admin/newsletters/drafts"));
+    }
+    FlashMessage::info("The draft has been saved.").send();
+    Ok(see_other(&draft_page))
+}
+
+#[tracing::instrument(name = "Send a test of a newsletter draft", skip(email_client, pool))]
+#[post("/newsletters/drafts/{draft_id}/test")]
+pub async fn send_newsletter_draft_test(
+    draft_id: web::Path<Uuid>,
+    user_id: web::ReqData<UserId>,
+    email_client: web::Data<dyn EmailSender>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let draft_id = draft_id.into_inner();
+    let draft_page = format!("/admin/newsletters/drafts/{}", draft_id);
+    let draft = match get_draft(draft_id, &pool).await.map_err(e500)? {
+        Some(draft) => draft,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let user_id = *user_id.into_inner();
+    let recipient = get_user_email(user_id, &pool)
+        .await
+        .map_err(e500)?
+        .map(SubscriberEmail::parse);
+    let recipient = match recipient {
+        Some(Ok(recipient)) => recipient,
+        _ => {
+            FlashMessage::error(
+                r#"Set <a href="/admin/email">your email address</a> to receive test sends."#,
+            )
+            .send();
+            return Ok(see_other(&draft_page));
+        }
+    };
+    let (html_template, text_template) = match (
+        NewsletterTemplate::parse(&draft.html_content),
+        NewsletterTemplate::parse(&draft.text_content),
+    ) {
+        (Ok(html_template), Ok(text_template)) => (html_template, text_template),
+        (Err(e), _) | (_, Err(e)) => {
+            FlashMessage::error(format!(
+                "The draft has an invalid placeholder. {}",
+                escape_html(&e)
+            ))
+            .send();
+            return Ok(see_other(&draft_page));
+        }
+    };
+    // The admin is not a subscriber, so there is no unsubscribe link to fill in
+    let username = get_username(user_id, &pool).await.map_err(e500)?;
+    let merge_fields = MergeFields {
+        name: &username,
+        email: recipient.as_ref(),
+        unsubscribe_url: "#",
+    };
+    email_client
+        .send_email(
+            &recipient,
+            &format!("[TEST] {}", draft.title),
+            &html_template.render_html(&merge_fields),
+            &text_template.render_text(&merge_fields),
+        )
+        .await
+        .context("Failed to send a test of a newsletter draft.")
+        .map_err(e500)?;
+    FlashMessage::info(format!(
+        "A test email has been sent to {}.",
+        escape_html(recipient.as_ref())
+    ))
+    .send();
+    Ok(see_other(&draft_page))
+}
@@ -0,0 +1,136 @@
+use crate::utils::{e500, escape_html};
+use actix_web::{get, http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{IncomingFlashMessages, Level};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+struct DeliveryFailure {
+    subscriber_email: String,
+    n_attempts: i16,
+    error: String,
+    failed_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "List failed newsletter deliveries", skip(pool, flash_messages))]
+#[get("/newsletters/{newsletter_issue_id}/failures")]
+pub async fn delivery_failures(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let title = match get_issue_title(newsletter_issue_id, &pool)
+        .await
+        .map_err(e500)?
+    {
+        Some(title) => title,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let failures = get_delivery_failures(newsletter_issue_id, &pool)
+        .await
+        .map_err(e500)?;
+
+    let mut message_html = String::new();
+    for message in flash_messages
+        .iter()
+        .filter(|m| m.level() == Level::Error || m.level() == Level::Info)
+    {
+        writeln!(message_html, "<p><i>{}</i></p>", message.content()).unwrap();
+    }
+    let mut rows_html = String::new();
+    for failure in &failures {
+        let email = escape_html(&failure.subscriber_email);
+        writeln!(
+            rows_html,
+            r#"<tr>
+                <td><input type="checkbox" name="subscriber_email" value="{email}"></td>
+                <td>{email}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td><pre>{}</pre></td>
+            </tr>"#,
+            failure.n_attempts,
+            failure.failed_at.to_rfc3339(),
+            escape_html(&failure.error),
+        )
+        .unwrap();
+    }
+    let title = escape_html(&title);
+    let n_failures = failures.len();
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Failed deliveries</title>
+            </head>
+            <body>
+                {message_html}
+                <h1>Failed deliveries for "{title}"</h1>
+                <p>{n_failures} failed deliveries.</p>
+                <form action="/admin/newsletters/{newsletter_issue_id}/failures" method="post">
+                    <table>
+                        <tr>
+                            <th></th>
+                            <th>Subscriber</th>
+                            <th>Attempts</th>
+                            <th>Failed at</th>
+                            <th>Error</th>
+                        </tr>
+                        {rows_html}
+                    </table>
+                    <button type="submit">Requeue selected</button>
+                </form>
+                <p><a href="/admin/dashboard">&lt;- Back</a></p>
+            </body>
+            </html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "Get newsletter issue title", skip(pool))]
+pub async fn get_issue_title(
+    newsletter_issue_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT title
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve a newsletter issue title.")?;
+
+    Ok(row.map(|r| r.title))
+}
+
+#[tracing::instrument(name = "Get failed newsletter deliveries", skip(pool))]
+async fn get_delivery_failures(
+    newsletter_issue_id: Uuid,
+    pool: &PgPool,
+) -> Result<Vec<DeliveryFailure>, anyhow::Error> {
+    let failures = sqlx::query_as!(
+        DeliveryFailure,
+        r#"
+        SELECT subscriber_email, n_attempts, error, failed_at
+        FROM issue_delivery_failures
+        WHERE newsletter_issue_id = $1
+        ORDER BY failed_at DESC
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve failed deliveries.")?;
+
+    Ok(failures)
+}
@@ -0,0 +1,5 @@
+mod get;
+mod post;
+
+pub use get::delivery_failures;
+pub use post::requeue_delivery_failures;
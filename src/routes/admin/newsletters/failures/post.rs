@@ -0,0 +1,112 @@
+use crate::utils::{e500, see_other};
+use actix_web::{post, web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use actix_web_lab::extract::UrlEncodedForm;
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+// Checkboxes submit one `subscriber_email` pair per selected row, which the default
+// `web::Form` extractor can not collect into a Vec.
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    #[serde(default)]
+    subscriber_email: Vec<String>,
+}
+
+#[tracing::instrument(name = "Requeue failed newsletter deliveries", skip(form, pool))]
+#[post("/newsletters/{newsletter_issue_id}/failures")]
+pub async fn requeue_delivery_failures(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: UrlEncodedForm<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let location = format!("/admin/newsletters/{}/failures", newsletter_issue_id);
+    let subscriber_emails = form.0.subscriber_email;
+
+    if subscriber_emails.is_empty() {
+        FlashMessage::error("Select at least one failed delivery to requeue.").send();
+        return Ok(see_other(&location));
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    let n_requeued = requeue_failures(&mut transaction, newsletter_issue_id, &subscriber_emails)
+        .await
+        .context("Failed to requeue failed deliveries.")
+        .map_err(e500)?;
+    reopen_issue_delivery(&mut transaction, newsletter_issue_id, n_requeued)
+        .await
+        .context("Failed to update the delivery progress of the newsletter issue.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to requeue failed deliveries.")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!("{} deliveries have been requeued.", n_requeued)).send();
+    Ok(see_other(&location))
+}
+
+// Deliveries to subscribers who have unsubscribed since are left in the failed deliveries.
+// A delivery that is already queued again still counts as requeued, it is pending either way.
+#[tracing::instrument(name = "Move failed deliveries back to the queue", skip(transaction))]
+async fn requeue_failures(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    subscriber_emails: &[String],
+) -> Result<u64, sqlx::Error> {
+    let n_requeued = sqlx::query!(
+        r#"
+        WITH requeued AS (
+            DELETE FROM issue_delivery_failures
+            WHERE
+                newsletter_issue_id = $1 AND
+                subscriber_email = ANY($2) AND
+                subscriber_email IN (
+                    SELECT email FROM subscriptions WHERE status = 'confirmed'
+                )
+            RETURNING newsletter_issue_id, subscriber_email
+        ), queued AS (
+            INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email)
+            SELECT newsletter_issue_id, subscriber_email
+            FROM requeued
+            ON CONFLICT DO NOTHING
+        )
+        SELECT COUNT(*) AS "n_requeued!" FROM requeued
+        "#,
+        newsletter_issue_id,
+        subscriber_emails
+    )
+    .fetch_one(transaction)
+    .await?
+    .n_requeued;
+    Ok(n_requeued as u64)
+}
+
+#[tracing::instrument(name = "Move requeued deliveries back to pending", skip(transaction))]
+async fn reopen_issue_delivery(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    n_requeued: u64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            n_failed = n_failed - $2,
+            delivery_completed_at = CASE WHEN $2 > 0 THEN NULL ELSE delivery_completed_at END
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        n_requeued as i32
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
@@ -1,9 +1,16 @@
-use actix_web::{get, http::header::ContentType, HttpResponse};
+use crate::routes::admin::newsletters::issue::list_issue_progress;
+use crate::routes::admin::{get_subscriber_lists, list_checkboxes_html, SubscriberList};
+use crate::utils::{e500, escape_html};
+use actix_web::{get, http::header::ContentType, web, HttpResponse};
 use actix_web_flash_messages::{IncomingFlashMessages, Level};
+use sqlx::PgPool;
 use std::fmt::Write;
 
 #[get("/newsletters")]
-pub async fn publish_newsletter_form(flash_messages: IncomingFlashMessages) -> HttpResponse {
+pub async fn publish_newsletter_form(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
     let mut error_html = String::new();
     let idempotency_key = uuid::Uuid::new_v4();
     for message in flash_messages
@@ -12,8 +19,33 @@ pub async fn publish_newsletter_form(flash_messages: IncomingFlashMessages) -> H
     {
         writeln!(error_html, "<p><i>{}</i></p>", message.content()).unwrap();
     }
+    let audience_html = audience_html(&get_subscriber_lists(&pool).await.map_err(e500)?);
+    let mut issues_html = String::new();
+    for issue in list_issue_progress(&pool).await.map_err(e500)? {
+        writeln!(
+            issues_html,
+            r#"<tr>
+                <td><a href="/admin/newsletters/{}">{}</a></td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>"#,
+            issue.newsletter_issue_id,
+            escape_html(&issue.title),
+            issue.published_at.to_rfc3339(),
+            issue.scheduled_for(),
+            issue.status(),
+            issue.n_pending(),
+            issue.n_delivered,
+            issue.n_failed,
+        )
+        .unwrap();
+    }
 
-    HttpResponse::Ok()
+    Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
         .body(format!(
             r#"<!DOCTYPE html>
@@ -24,6 +56,7 @@ pub async fn publish_newsletter_form(flash_messages: IncomingFlashMessages) -> H
             </head>
             <body>
                 {error_html}
+                <p>Personalise the content with the {{{{ name }}}}, {{{{ email }}}} and {{{{ unsubscribe_url }}}} merge fields.</p>
                 <form action="/admin/newsletters" method="post">
                     <label>Title:<br>
                         <input
@@ -33,7 +66,21 @@ pub async fn publish_newsletter_form(flash_messages: IncomingFlashMessages) -> H
                         >
                     </label>
                     <br>
-                    <label>Plain text content:<br>
+                    <fieldset>
+                        <legend>Format:</legend>
+                        <label><input type="radio" name="format" value="html" checked> HTML and plain text</label>
+                        <label><input type="radio" name="format" value="markdown"> Markdown</label>
+                    </fieldset>
+                    <label>Markdown content (the HTML and plain text content are rendered from it):<br>
+                        <textarea
+                            placeholder="Enter the content in Markdown"
+                            name="markdown_content"
+                            rows="20"
+                            cols="50"
+                        ></textarea>
+                    </label>
+                    <br>
+                    <label>Plain text content (leave empty to derive it from the HTML content):<br>
                         <textarea
                             placeholder="Enter the content in plain text"
                             name="text_content"
@@ -51,10 +98,44 @@ pub async fn publish_newsletter_form(flash_messages: IncomingFlashMessages) -> H
                         ></textarea>
                     </label>
                     <br>
+                    <label>Schedule for (UTC, leave empty to send right away):<br>
+                        <input type="datetime-local" name="scheduled_for">
+                    </label>
+                    <br>
+                    {audience_html}
                     <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
-                    <button type="submit">Login</button>
+                    <button type="submit">Publish</button>
+                    <button type="submit" formaction="/admin/newsletters/drafts">Save as draft</button>
                 </form>
+                <p><a href="/admin/newsletters/drafts">Drafts</a></p>
+                <h2>Published issues</h2>
+                <table>
+                    <tr>
+                        <th>Title</th>
+                        <th>Published at</th>
+                        <th>Scheduled for</th>
+                        <th>Status</th>
+                        <th>Pending</th>
+                        <th>Sent</th>
+                        <th>Failed</th>
+                    </tr>
+                    {issues_html}
+                </table>
             </body>
             </html>"#,
-        ))
+        )))
+}
+
+// Lets the author send an issue to some lists only, nothing is shown until lists are created
+pub fn audience_html(lists: &[SubscriberList]) -> String {
+    if lists.is_empty() {
+        return String::new();
+    }
+    format!(
+        r#"<fieldset>
+                        <legend>Send to (leave unchecked to send to every confirmed subscriber):</legend>
+                        {}
+                    </fieldset>"#,
+        list_checkboxes_html(lists, &[])
+    )
 }
@@ -0,0 +1,215 @@
+use crate::routes::admin::newsletters::schedule::format_scheduled_for;
+use crate::utils::{e500, escape_html};
+use actix_web::{get, http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::{IncomingFlashMessages, Level};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+pub struct IssueDeliveryProgress {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub published_at: DateTime<Utc>,
+    pub n_recipients: i32,
+    pub n_delivered: i32,
+    pub n_failed: i32,
+    pub delivery_completed_at: Option<DateTime<Utc>>,
+    pub scheduled_for: Option<DateTime<Utc>>,
+    pub in_archive: bool,
+}
+
+impl IssueDeliveryProgress {
+    pub fn n_pending(&self) -> i32 {
+        self.n_recipients - self.n_delivered - self.n_failed
+    }
+
+    // Scheduled issues can still be edited or cancelled, nothing has been sent for them yet
+    pub fn is_scheduled(&self) -> bool {
+        self.scheduled_for.is_some_and(|t| t > Utc::now())
+    }
+
+    pub fn status(&self) -> &'static str {
+        if self.is_scheduled() {
+            return "scheduled";
+        }
+        match self.delivery_completed_at {
+            Some(_) => "completed",
+            None => "in progress",
+        }
+    }
+
+    pub fn scheduled_for(&self) -> String {
+        self.scheduled_for
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_else(|| "-".into())
+    }
+}
+
+#[tracing::instrument(
+    name = "Show newsletter issue delivery progress",
+    skip(flash_messages, pool)
+)]
+#[get("/newsletters/{newsletter_issue_id}")]
+pub async fn newsletter_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = match get_issue_progress(newsletter_issue_id.into_inner(), &pool)
+        .await
+        .map_err(e500)?
+    {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let title = escape_html(&issue.title);
+    let completed_at = issue
+        .delivery_completed_at
+        .map(|t| t.to_rfc3339())
+        .unwrap_or_else(|| "-".into());
+    let mut message_html = String::new();
+    for message in flash_messages
+        .iter()
+        .filter(|m| m.level() == Level::Error || m.level() == Level::Info)
+    {
+        writeln!(message_html, "<p><i>{}</i></p>", message.content()).unwrap();
+    }
+    let schedule_html = match issue.scheduled_for {
+        Some(scheduled_for) if issue.is_scheduled() => format!(
+            r#"<form action="/admin/newsletters/{id}/schedule" method="post">
+                    <label>Reschedule for (UTC):
+                        <input
+                            type="datetime-local"
+                            name="scheduled_for"
+                            value="{}"
+                        >
+                    </label>
+                    <button type="submit">Reschedule</button>
+                </form>
+                <p><a href="/admin/newsletters/{id}/edit">Edit content</a></p>
+                <form action="/admin/newsletters/{id}/cancel" method="post">
+                    <button type="submit">Cancel issue</button>
+                </form>"#,
+            format_scheduled_for(scheduled_for),
+            id = issue.newsletter_issue_id,
+        ),
+        _ => String::new(),
+    };
+
+    let archive_html = match issue.in_archive {
+        true => format!(
+            r#"<p>Listed in the <a href="/archive/{id}">public archive</a>.</p>
+                <form action="/admin/newsletters/{id}/archive" method="post">
+                    <input hidden type="text" name="in_archive" value="false">
+                    <button type="submit">Hide from the archive</button>
+                </form>"#,
+            id = issue.newsletter_issue_id,
+        ),
+        false => format!(
+            r#"<p>Hidden from the public archive.</p>
+                <form action="/admin/newsletters/{id}/archive" method="post">
+                    <input hidden type="text" name="in_archive" value="true">
+                    <button type="submit">Show in the archive</button>
+                </form>"#,
+            id = issue.newsletter_issue_id,
+        ),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Newsletter issue</title>
+            </head>
+            <body>
+                {message_html}
+                <h1>{title}</h1>
+                <p>Published at: {}</p>
+                <p>Scheduled for: {}</p>
+                <p>Status: {}</p>
+                <ul>
+                    <li>Recipients: {}</li>
+                    <li>Pending: {}</li>
+                    <li>Sent: {}</li>
+                    <li>Failed: <a href="/admin/newsletters/{}/failures">{}</a></li>
+                </ul>
+                <p>Completed at: {completed_at}</p>
+                {schedule_html}
+                {archive_html}
+                <p><a href="/admin/newsletters">&lt;- Back</a></p>
+            </body>
+            </html>"#,
+            issue.published_at.to_rfc3339(),
+            issue.scheduled_for(),
+            issue.status(),
+            issue.n_recipients,
+            issue.n_pending(),
+            issue.n_delivered,
+            issue.newsletter_issue_id,
+            issue.n_failed,
+        )))
+}
+
+#[tracing::instrument(name = "Get newsletter issue delivery progress", skip(pool))]
+async fn get_issue_progress(
+    newsletter_issue_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<IssueDeliveryProgress>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        IssueDeliveryProgress,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            published_at as "published_at!",
+            n_recipients,
+            n_delivered,
+            n_failed,
+            delivery_completed_at,
+            scheduled_for,
+            in_archive
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND published_at IS NOT NULL
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve a newsletter issue.")?;
+
+    Ok(issue)
+}
+
+#[tracing::instrument(name = "List newsletter issues delivery progress", skip(pool))]
+pub async fn list_issue_progress(
+    pool: &PgPool,
+) -> Result<Vec<IssueDeliveryProgress>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        IssueDeliveryProgress,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            published_at as "published_at!",
+            n_recipients,
+            n_delivered,
+            n_failed,
+            delivery_completed_at,
+            scheduled_for,
+            in_archive
+        FROM newsletter_issues
+        WHERE published_at IS NOT NULL
+        ORDER BY GREATEST(published_at, scheduled_for) DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve newsletter issues.")?;
+
+    Ok(issues)
+}
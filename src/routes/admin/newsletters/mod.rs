@@ -1,5 +1,21 @@
+mod archive;
+mod drafts;
+mod failures;
 mod get;
+mod issue;
 mod post;
+mod schedule;
 
+pub use archive::set_newsletter_issue_in_archive;
+pub use drafts::{
+    newsletter_draft, newsletter_drafts, preview_newsletter_draft, save_newsletter_draft,
+    send_newsletter_draft_test, update_newsletter_draft,
+};
+pub use failures::{delivery_failures, requeue_delivery_failures};
 pub use get::publish_newsletter_form;
+pub use issue::newsletter_issue;
 pub use post::publish_newsletter;
+pub use schedule::{
+    cancel_newsletter_issue, edit_newsletter_issue, edit_newsletter_issue_form,
+    reschedule_newsletter_issue,
+};
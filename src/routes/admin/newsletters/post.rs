@@ -1,20 +1,58 @@
 use crate::authentication::UserId;
-use crate::email_client::EmailClient;
+use crate::domain::NewsletterTemplate;
+use crate::email_client::EmailSender;
+use crate::html_sanitizer::sanitize_html;
+use crate::html_to_text::html_to_text;
 use crate::idempotency::{save_response, try_processing, IdempotencyKey, NextAction};
 use crate::issue_delivery_worker::Newsletter;
-use crate::utils::{e400, e500, see_other};
+use crate::markdown::markdown_to_html;
+use crate::routes::admin::newsletters::schedule::parse_scheduled_for;
+use crate::utils::{e400, e500, escape_html, see_other};
 use actix_web::{post, web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
+use actix_web_lab::extract::UrlEncodedForm;
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+#[derive(serde::Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentFormat {
+    #[default]
+    Html,
+    // The HTML and plain text bodies are both rendered from `markdown_content`
+    Markdown,
+}
+
 #[derive(serde::Deserialize)]
 pub struct FormData {
     title: String,
+    #[serde(default)]
+    format: ContentFormat,
+    #[serde(default)]
     html_content: String,
+    // Derived from the HTML content when left empty
+    #[serde(default)]
     text_content: String,
+    #[serde(default)]
+    markdown_content: String,
     idempotency_key: String,
+    // Left empty to send the issue right away
+    #[serde(default)]
+    scheduled_for: String,
+    // Set when publishing a previously saved draft
+    draft_id: Option<Uuid>,
+    // The lists the issue is sent to, every confirmed subscriber gets it when none is selected
+    #[serde(default)]
+    list_id: Vec<Uuid>,
+}
+
+// Returns the HTML and plain text bodies of an issue written in Markdown
+pub fn render_markdown(markdown: &str) -> (String, String) {
+    let html_content = markdown_to_html(markdown);
+    let text_content = html_to_text(&html_content);
+    (html_content, text_content)
 }
 
 // Genereally we want both empty field to return bad request but because we want to redirect
@@ -24,33 +62,86 @@ impl TryFrom<FormData> for Newsletter {
     fn try_from(form: FormData) -> Result<Self, Self::Error> {
         let FormData {
             title,
+            format,
             html_content,
             text_content,
+            markdown_content,
             ..
         } = form;
-        if title.trim().is_empty() {
-            FlashMessage::error("Title cannot be empty.").send();
-            anyhow::bail!("Title is empty")
-        } else if html_content.trim().is_empty() {
-            FlashMessage::error("Html content cannot be empty.").send();
-            anyhow::bail!("Html content is empty")
-        } else if text_content.trim().is_empty() {
-            FlashMessage::error("Plain text content cannot be empty.").send();
-            anyhow::bail!("Plain text content is empty")
-        } else {
-            Ok(Self {
-                title,
-                html_content,
-                text_content,
-            })
-        }
+        parse_newsletter(title, format, html_content, text_content, markdown_content)
     }
 }
 
-impl From<Newsletter> for FormData {
-    fn from(nl: Newsletter) -> Self {
-        nl.into()
+// Also used when the content of a scheduled issue is edited. Every problem is reported as an
+// error flash message.
+pub fn parse_newsletter(
+    title: String,
+    format: ContentFormat,
+    html_content: String,
+    mut text_content: String,
+    markdown_content: String,
+) -> Result<Newsletter, anyhow::Error> {
+    if title.trim().is_empty() {
+        FlashMessage::error("Title cannot be empty.").send();
+        anyhow::bail!("Title is empty")
     }
+    let (html_content, text_content, markdown_content) = match format {
+        ContentFormat::Markdown => {
+            if markdown_content.trim().is_empty() {
+                FlashMessage::error("Markdown content cannot be empty.").send();
+                anyhow::bail!("Markdown content is empty")
+            }
+            let (html_content, text_content) = render_markdown(&markdown_content);
+            let html_content = sanitize(&html_content)?;
+            (html_content, text_content, Some(markdown_content))
+        }
+        ContentFormat::Html => {
+            if html_content.trim().is_empty() {
+                FlashMessage::error("Html content cannot be empty.").send();
+                anyhow::bail!("Html content is empty")
+            }
+            let html_content = sanitize(&html_content)?;
+            if text_content.trim().is_empty() {
+                text_content = html_to_text(&html_content);
+            }
+            if text_content.trim().is_empty() {
+                FlashMessage::error(
+                    "Plain text content cannot be empty, the HTML content has no text to derive it from.",
+                )
+                .send();
+                anyhow::bail!("Plain text content is empty")
+            }
+            (html_content, text_content, None)
+        }
+    };
+    // Catch broken placeholders now rather than when the worker renders the issue
+    for (content_type, content) in [("Html", &html_content), ("Plain text", &text_content)] {
+        if let Err(e) = NewsletterTemplate::parse(content) {
+            FlashMessage::error(format!(
+                "{} content has an invalid placeholder. {}",
+                content_type,
+                escape_html(&e)
+            ))
+            .send();
+            anyhow::bail!("{} content is not a valid template: {}", content_type, e)
+        }
+    }
+    Ok(Newsletter {
+        title,
+        html_content,
+        text_content,
+        markdown_content,
+    })
+}
+
+// Every problem is reported at once so the author can fix them all before publishing again
+fn sanitize(html_content: &str) -> Result<String, anyhow::Error> {
+    sanitize_html(html_content).map_err(|problems| {
+        for problem in &problems {
+            FlashMessage::error(escape_html(&problem.to_string())).send();
+        }
+        anyhow::anyhow!("Html content has {} problem(s)", problems.len())
+    })
 }
 
 #[tracing::instrument(
@@ -59,17 +150,39 @@ impl From<Newsletter> for FormData {
 )]
 #[post("/newsletters")]
 pub async fn publish_newsletter(
-    form: web::Form<FormData>,
+    form: UrlEncodedForm<FormData>,
     user_id: web::ReqData<UserId>,
-    _email_client: web::Data<EmailClient>,
+    _email_client: web::Data<dyn EmailSender>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let idempotency_key: IdempotencyKey =
         form.idempotency_key.to_owned().try_into().map_err(e400)?;
     let user_id = user_id.into_inner();
 
+    // Send the user back to the form they submitted if something is wrong with it
+    let draft_id = form.draft_id;
+    let form_page = match draft_id {
+        Some(draft_id) => format!("/admin/newsletters/drafts/{}", draft_id),
+        None => "/admin/newsletters".into(),
+    };
+    let scheduled_for = match parse_scheduled_for(&form.scheduled_for) {
+        Ok(scheduled_for) => scheduled_for,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&form_page));
+        }
+    };
+    let list_ids = form.list_id.clone();
+    let n_lists = count_lists(&pool, &list_ids)
+        .await
+        .context("Failed to check the selected lists.")
+        .map_err(e500)?;
+    if n_lists != list_ids.len() as i64 {
+        FlashMessage::error("Some of the selected lists no longer exist.").send();
+        return Ok(see_other(&form_page));
+    }
     let newsletter: Newsletter = match form.0.try_into() {
-        Err(_) => return Ok(see_other("/admin/newsletters")),
+        Err(_) => return Ok(see_other(&form_page)),
         Ok(newsletter) => newsletter,
     };
 
@@ -80,17 +193,35 @@ pub async fn publish_newsletter(
     {
         NextAction::StartProcessing(transaction) => transaction,
         NextAction::ReturnSavedResponse(saved_response) => {
-            success_message().send();
+            success_message(scheduled_for).send();
             return Ok(saved_response);
         }
     };
-    let issue_id = insert_newsletter_issue(&mut transaction, &newsletter)
+    let issue_id = match draft_id {
+        Some(draft_id) => {
+            let is_published =
+                publish_draft(&mut transaction, draft_id, &newsletter, scheduled_for)
+                    .await
+                    .context("Failed to publish a newsletter draft.")
+                    .map_err(e500)?;
+            if !is_published {
+                FlashMessage::error("This draft has already been published.").send();
+                return Ok(see_other("/admin/newsletters"));
+            }
+            draft_id
+        }
+        None => insert_newsletter_issue(&mut transaction, &newsletter, scheduled_for)
+            .await
+            .context("Failed to store newsletter issue details.")
+            .map_err(e500)?,
+    };
+    let n_recipients = enqueue_delivery_task(&mut transaction, issue_id, &list_ids)
         .await
-        .context("Failed to store newsletter issue details.")
+        .context("Failed to enqueue delivery tasks.")
         .map_err(e500)?;
-    enqueue_delivery_task(&mut transaction, issue_id)
+    set_issue_recipients(&mut transaction, issue_id, n_recipients)
         .await
-        .context("Failed to enqueue delivery tasks.")
+        .context("Failed to store the number of recipients.")
         .map_err(e500)?;
     let response = see_other("/admin/newsletters");
     // save response dissect the response store it in the database then reassemble it into a new
@@ -98,7 +229,7 @@ pub async fn publish_newsletter(
     let response = save_response(*user_id, &idempotency_key, response, transaction)
         .await
         .map_err(e500)?;
-    success_message().send();
+    success_message(scheduled_for).send();
     Ok(response)
 }
 
@@ -106,6 +237,7 @@ pub async fn publish_newsletter(
 async fn insert_newsletter_issue(
     transaction: &mut Transaction<'_, Postgres>,
     newsletter: &Newsletter,
+    scheduled_for: Option<DateTime<Utc>>,
 ) -> Result<Uuid, sqlx::Error> {
     let newsletter_issue_id = Uuid::new_v4();
     sqlx::query!(
@@ -115,42 +247,135 @@ async fn insert_newsletter_issue(
             title,
             html_content,
             text_content,
-            published_at
+            markdown_content,
+            published_at,
+            scheduled_for
         )
-        VALUES ($1, $2, $3, $4, now())
+        VALUES ($1, $2, $3, $4, $5, now(), $6)
         "#,
         newsletter_issue_id,
         newsletter.title,
         newsletter.html_content,
-        newsletter.text_content
+        newsletter.text_content,
+        newsletter.markdown_content,
+        scheduled_for
     )
     .execute(transaction)
     .await?;
     Ok(newsletter_issue_id)
 }
 
+// The draft is saved with the submitted content before being published. Returns false if there is
+// no draft with this id, e.g. because it has been published already.
+#[tracing::instrument(name = "Publishing newsletter draft.", skip(transaction, newsletter))]
+async fn publish_draft(
+    transaction: &mut Transaction<'_, Postgres>,
+    draft_id: Uuid,
+    newsletter: &Newsletter,
+    scheduled_for: Option<DateTime<Utc>>,
+) -> Result<bool, sqlx::Error> {
+    let n_published = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            title = $2,
+            html_content = $3,
+            text_content = $4,
+            markdown_content = $5,
+            published_at = now(),
+            scheduled_for = $6
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL
+        "#,
+        draft_id,
+        newsletter.title,
+        newsletter.html_content,
+        newsletter.text_content,
+        newsletter.markdown_content,
+        scheduled_for
+    )
+    .execute(transaction)
+    .await?
+    .rows_affected();
+    Ok(n_published == 1)
+}
+
+#[tracing::instrument(name = "Counting the selected lists.", skip(pool))]
+async fn count_lists(pool: &PgPool, list_ids: &[Uuid]) -> Result<i64, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT COUNT(*) as "n_lists!" FROM lists WHERE list_id = ANY($1)"#,
+        list_ids
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.n_lists)
+}
+
+// Members of several of the lists are only enqueued once
 #[tracing::instrument(name = "Enqueuing delivery task in the database.", skip(transaction))]
 async fn enqueue_delivery_task(
     transaction: &mut Transaction<'_, Postgres>,
     newsletter_issue_id: Uuid,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+    list_ids: &[Uuid],
+) -> Result<u64, sqlx::Error> {
+    let n_enqueued = sqlx::query!(
         r#"
         INSERT INTO issue_delivery_queue (
             newsletter_issue_id,
             subscriber_email
         )
-        SELECT $1, email 
-        FROM subscriptions
-        WHERE status = 'confirmed'
+        SELECT $1, s.email
+        FROM subscriptions s
+        WHERE
+            s.status = 'confirmed' AND
+            (
+                cardinality($2::uuid[]) = 0 OR
+                EXISTS (
+                    SELECT 1 FROM subscriptions_lists sl
+                    WHERE sl.subscriber_id = s.id AND sl.list_id = ANY($2)
+                )
+            )
         "#,
         newsletter_issue_id,
+        list_ids
+    )
+    .execute(transaction)
+    .await?
+    .rows_affected();
+    Ok(n_enqueued)
+}
+
+#[tracing::instrument(
+    name = "Store the number of recipients of an issue.",
+    skip(transaction)
+)]
+async fn set_issue_recipients(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    n_recipients: u64,
+) -> Result<(), sqlx::Error> {
+    // An issue with nobody to deliver to is done as soon as it is published
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            n_recipients = $2,
+            delivery_completed_at = CASE WHEN $2 = 0 THEN now() ELSE NULL END
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        n_recipients as i32
     )
     .execute(transaction)
     .await?;
     Ok(())
 }
 
-fn success_message() -> FlashMessage {
-    FlashMessage::info("The newsletter issue has been published!")
+fn success_message(scheduled_for: Option<DateTime<Utc>>) -> FlashMessage {
+    match scheduled_for {
+        Some(scheduled_for) => FlashMessage::info(format!(
+            "The newsletter issue has been scheduled for {}.",
+            scheduled_for.to_rfc3339()
+        )),
+        None => FlashMessage::info("The newsletter issue has been published!"),
+    }
 }
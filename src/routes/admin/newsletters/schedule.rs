@@ -0,0 +1,363 @@
+use crate::issue_delivery_worker::Newsletter;
+use crate::routes::admin::newsletters::drafts::content_fields_html;
+use crate::routes::admin::newsletters::post::{parse_newsletter, ContentFormat};
+use crate::utils::{e500, escape_html, see_other};
+use actix_web::{get, http::header::ContentType, post, web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages, Level};
+use anyhow::Context;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::fmt::Write;
+use uuid::Uuid;
+
+// `<input type="datetime-local">` submits the time without seconds or an offset, we treat it as UTC
+const DATETIME_LOCAL_FORMATS: [&str; 2] = ["%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S"];
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    scheduled_for: String,
+}
+
+// Same fields as the draft editor
+#[derive(serde::Deserialize)]
+pub struct ContentFormData {
+    title: String,
+    #[serde(default)]
+    format: ContentFormat,
+    #[serde(default)]
+    html_content: String,
+    #[serde(default)]
+    text_content: String,
+    #[serde(default)]
+    markdown_content: String,
+}
+
+// An empty field means "send right away"
+pub fn parse_scheduled_for(raw: &str) -> Result<Option<DateTime<Utc>>, String> {
+    let raw = raw.trim();
+    if raw.is_empty() {
+        return Ok(None);
+    }
+    let scheduled_for = DATETIME_LOCAL_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(raw, format).ok())
+        .map(|t| Utc.from_utc_datetime(&t))
+        .ok_or_else(|| format!("{} is not a valid scheduled time.", escape_html(raw)))?;
+    if scheduled_for <= Utc::now() {
+        return Err("The scheduled time must be in the future.".into());
+    }
+    Ok(Some(scheduled_for))
+}
+
+// Used by forms pre-filled with the current scheduled time
+pub fn format_scheduled_for(scheduled_for: DateTime<Utc>) -> String {
+    scheduled_for.format(DATETIME_LOCAL_FORMATS[0]).to_string()
+}
+
+#[tracing::instrument(name = "Reschedule a newsletter issue", skip(form, pool))]
+#[post("/newsletters/{newsletter_issue_id}/schedule")]
+pub async fn reschedule_newsletter_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let issue_page = format!("/admin/newsletters/{}", newsletter_issue_id);
+    let scheduled_for = match parse_scheduled_for(&form.scheduled_for) {
+        Ok(Some(scheduled_for)) => scheduled_for,
+        Ok(None) => {
+            FlashMessage::error("Pick a time to reschedule the issue to.").send();
+            return Ok(see_other(&issue_page));
+        }
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other(&issue_page));
+        }
+    };
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    if !lock_pending_issue(&mut transaction, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("Only issues that have not gone out yet can be rescheduled.").send();
+        return Ok(see_other(&issue_page));
+    }
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_for = $2
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        scheduled_for
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update the scheduled time of a newsletter issue.")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reschedule a newsletter issue.")
+        .map_err(e500)?;
+    FlashMessage::info(format!(
+        "The newsletter issue has been rescheduled for {}.",
+        scheduled_for.to_rfc3339()
+    ))
+    .send();
+    Ok(see_other(&issue_page))
+}
+
+#[tracing::instrument(name = "Cancel a scheduled newsletter issue", skip(pool))]
+#[post("/newsletters/{newsletter_issue_id}/cancel")]
+pub async fn cancel_newsletter_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    if !lock_pending_issue(&mut transaction, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("Only issues that have not gone out yet can be cancelled.").send();
+        return Ok(see_other(&format!(
+            "/admin/newsletters/{}",
+            newsletter_issue_id
+        )));
+    }
+    delete_issue(&mut transaction, newsletter_issue_id)
+        .await
+        .context("Failed to delete a scheduled newsletter issue.")
+        .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to cancel a newsletter issue.")
+        .map_err(e500)?;
+    FlashMessage::info("The scheduled newsletter issue has been cancelled.").send();
+    Ok(see_other("/admin/newsletters"))
+}
+
+#[tracing::instrument(
+    name = "Show the editor of a scheduled newsletter issue",
+    skip(flash_messages, pool)
+)]
+#[get("/newsletters/{newsletter_issue_id}/edit")]
+pub async fn edit_newsletter_issue_form(
+    newsletter_issue_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let issue = match get_pending_issue(newsletter_issue_id, &pool)
+        .await
+        .map_err(e500)?
+    {
+        Some(issue) => issue,
+        None => {
+            FlashMessage::error("Only issues that have not gone out yet can be edited.").send();
+            return Ok(see_other(&format!(
+                "/admin/newsletters/{}",
+                newsletter_issue_id
+            )));
+        }
+    };
+    let mut message_html = String::new();
+    for message in flash_messages
+        .iter()
+        .filter(|m| m.level() == Level::Error || m.level() == Level::Info)
+    {
+        writeln!(message_html, "<p><i>{}</i></p>", message.content()).unwrap();
+    }
+    let content_fields_html = content_fields_html(&issue);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Edit newsletter issue</title>
+            </head>
+            <body>
+                {message_html}
+                <p>Personalise the content with the {{{{ name }}}}, {{{{ email }}}} and {{{{ unsubscribe_url }}}} merge fields.</p>
+                <form action="/admin/newsletters/{newsletter_issue_id}/edit" method="post">
+                    {content_fields_html}
+                    <button type="submit">Save</button>
+                </form>
+                <p><a href="/admin/newsletters/{newsletter_issue_id}">&lt;- Back</a></p>
+            </body>
+            </html>"#,
+        )))
+}
+
+// The content goes through the same checks as when the issue was published
+#[tracing::instrument(name = "Edit a scheduled newsletter issue", skip(form, pool))]
+#[post("/newsletters/{newsletter_issue_id}/edit")]
+pub async fn edit_newsletter_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    form: web::Form<ContentFormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let issue_page = format!("/admin/newsletters/{}", newsletter_issue_id);
+    let ContentFormData {
+        title,
+        format,
+        html_content,
+        text_content,
+        markdown_content,
+    } = form.into_inner();
+    let newsletter =
+        match parse_newsletter(title, format, html_content, text_content, markdown_content) {
+            Ok(newsletter) => newsletter,
+            Err(_) => return Ok(see_other(&format!("{}/edit", issue_page))),
+        };
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")
+        .map_err(e500)?;
+    if !lock_pending_issue(&mut transaction, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::error("Only issues that have not gone out yet can be edited.").send();
+        return Ok(see_other(&issue_page));
+    }
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET
+            title = $2,
+            html_content = $3,
+            text_content = $4,
+            markdown_content = $5
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        newsletter.title,
+        newsletter.html_content,
+        newsletter.text_content,
+        newsletter.markdown_content
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update the content of a newsletter issue.")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to edit a newsletter issue.")
+        .map_err(e500)?;
+    FlashMessage::info("The newsletter issue has been updated.").send();
+    Ok(see_other(&issue_page))
+}
+
+#[tracing::instrument(name = "Get a scheduled newsletter issue", skip(pool))]
+async fn get_pending_issue(
+    newsletter_issue_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<Newsletter>, anyhow::Error> {
+    sqlx::query_as!(
+        Newsletter,
+        r#"
+        SELECT title, html_content, text_content, markdown_content
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND scheduled_for > now()
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve a scheduled newsletter issue.")
+}
+
+// Returns false if the issue does not exist or is already due, in which case the worker may be
+// delivering it and it can no longer be changed.
+#[tracing::instrument(name = "Lock a scheduled newsletter issue", skip(transaction))]
+async fn lock_pending_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1 AND scheduled_for > now()
+        FOR UPDATE
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(transaction)
+    .await
+    .context("Failed to lock a scheduled newsletter issue.")?;
+    Ok(row.is_some())
+}
+
+#[tracing::instrument(name = "Delete a newsletter issue", skip(transaction))]
+async fn delete_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_scheduled_for;
+    use chrono::{Duration, TimeZone, Utc};
+    use claims::{assert_err, assert_none, assert_some_eq};
+
+    #[test]
+    fn an_empty_scheduled_time_means_send_right_away() {
+        assert_none!(parse_scheduled_for("  ").unwrap());
+    }
+
+    #[test]
+    fn a_future_datetime_local_value_is_parsed_as_utc() {
+        let tomorrow = (Utc::now() + Duration::days(1)).date_naive();
+        let expected = Utc.from_utc_datetime(&tomorrow.and_hms_opt(9, 30, 0).unwrap());
+        let raw = expected.format("%Y-%m-%dT%H:%M").to_string();
+        assert_some_eq!(parse_scheduled_for(&raw).unwrap(), expected);
+    }
+
+    #[test]
+    fn a_scheduled_time_in_the_past_is_rejected() {
+        assert_err!(parse_scheduled_for("2020-01-01T09:30"));
+    }
+
+    #[test]
+    fn a_malformed_scheduled_time_is_rejected() {
+        assert_err!(parse_scheduled_for("tomorrow"));
+    }
+}
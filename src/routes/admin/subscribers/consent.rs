@@ -0,0 +1,210 @@
+use crate::utils::{e500, escape_html};
+use actix_web::http::header::{ContentDisposition, ContentType, DispositionParam, DispositionType};
+use actix_web::{get, web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, SecondsFormat, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+struct Subscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+struct ConsentEvent {
+    event_type: String,
+    email: String,
+    occurred_at: DateTime<Utc>,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+    source: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct ConsentExport {
+    subscriber: ExportedSubscriber,
+    consent_events: Vec<ExportedConsentEvent>,
+}
+
+#[derive(serde::Serialize)]
+struct ExportedSubscriber {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: String,
+}
+
+#[derive(serde::Serialize)]
+struct ExportedConsentEvent {
+    event_type: String,
+    email: String,
+    occurred_at: String,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+    source: Option<String>,
+}
+
+#[get("/subscribers/{subscriber_id}/consent")]
+pub async fn subscriber_consent_history(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let subscriber = match get_subscriber(subscriber_id, &pool).await.map_err(e500)? {
+        Some(subscriber) => subscriber,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let mut events_html = String::new();
+    for event in get_consent_events(subscriber_id, &pool)
+        .await
+        .map_err(e500)?
+    {
+        writeln!(
+            events_html,
+            r#"<tr>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+            </tr>"#,
+            event.occurred_at.to_rfc3339(),
+            event.event_type,
+            escape_html(&event.email),
+            escape_html(event.ip_address.as_deref().unwrap_or("unknown")),
+            escape_html(event.user_agent.as_deref().unwrap_or("unknown")),
+            escape_html(event.source.as_deref().unwrap_or("")),
+        )
+        .unwrap();
+    }
+    let email = escape_html(&subscriber.email);
+    let name = escape_html(&subscriber.name);
+    let status = subscriber.status;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Consent history</title>
+            </head>
+            <body>
+                <h1>Consent history of {email}</h1>
+                <p>Name: {name}</p>
+                <p>Status: {status}</p>
+                <table>
+                    <tr>
+                        <th>Date</th>
+                        <th>Event</th>
+                        <th>Email</th>
+                        <th>IP address</th>
+                        <th>User agent</th>
+                        <th>Source</th>
+                    </tr>
+                    {events_html}
+                </table>
+                <p><a href="/admin/subscribers/{subscriber_id}/consent.json">Export as JSON</a></p>
+                <p><a href="/admin/subscribers">&lt;- Back</a></p>
+            </body>
+            </html>"#,
+        )))
+}
+
+// The same history as a file, to hand over with a data subject or regulator request
+#[get("/subscribers/{subscriber_id}/consent.json")]
+pub async fn export_subscriber_consent(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let subscriber = match get_subscriber(subscriber_id, &pool).await.map_err(e500)? {
+        Some(subscriber) => subscriber,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let consent_events = get_consent_events(subscriber_id, &pool)
+        .await
+        .map_err(e500)?
+        .into_iter()
+        .map(|event| ExportedConsentEvent {
+            event_type: event.event_type,
+            email: event.email,
+            occurred_at: format_timestamp(event.occurred_at),
+            ip_address: event.ip_address,
+            user_agent: event.user_agent,
+            source: event.source,
+        })
+        .collect();
+    let export = ConsentExport {
+        subscriber: ExportedSubscriber {
+            id: subscriber.id,
+            email: subscriber.email,
+            name: subscriber.name,
+            status: subscriber.status,
+            subscribed_at: format_timestamp(subscriber.subscribed_at),
+        },
+        consent_events,
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(format!(
+                "consent-{}.json",
+                subscriber_id
+            ))],
+        })
+        .json(export))
+}
+
+fn format_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+#[tracing::instrument(name = "Get a subscriber", skip(pool))]
+async fn get_subscriber(
+    subscriber_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve a subscriber.")?;
+    Ok(subscriber)
+}
+
+#[tracing::instrument(name = "Get the consent events of a subscriber", skip(pool))]
+async fn get_consent_events(
+    subscriber_id: Uuid,
+    pool: &PgPool,
+) -> Result<Vec<ConsentEvent>, anyhow::Error> {
+    let events = sqlx::query_as!(
+        ConsentEvent,
+        r#"
+        SELECT event_type, email, occurred_at, ip_address, user_agent, source
+        FROM consent_events
+        WHERE subscriber_id = $1
+        ORDER BY occurred_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve consent events.")?;
+    Ok(events)
+}
@@ -0,0 +1,115 @@
+use crate::utils::{e500, escape_html};
+use actix_web::http::header::ContentType;
+use actix_web::{get, web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+const MAX_SUBSCRIBERS_SHOWN: i64 = 50;
+
+#[derive(serde::Deserialize)]
+pub struct SubscriberSearchParameters {
+    email: Option<String>,
+}
+
+struct SubscriberSummary {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[get("/subscribers")]
+pub async fn subscriber_search(
+    parameters: web::Query<SubscriberSearchParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let search = parameters
+        .email
+        .as_deref()
+        .map(str::trim)
+        .filter(|email| !email.is_empty());
+    let subscribers = search_subscribers(search, &pool).await.map_err(e500)?;
+    let mut subscribers_html = String::new();
+    for subscriber in &subscribers {
+        writeln!(
+            subscribers_html,
+            r#"<tr>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td>{}</td>
+                <td><a href="/admin/subscribers/{}/consent">Consent history</a></td>
+            </tr>"#,
+            escape_html(&subscriber.email),
+            escape_html(&subscriber.name),
+            subscriber.status,
+            subscriber.subscribed_at.to_rfc3339(),
+            subscriber.id
+        )
+        .unwrap();
+    }
+    if subscribers.is_empty() {
+        subscribers_html.push_str(r#"<tr><td colspan="5">No subscriber found.</td></tr>"#);
+    }
+    let search = escape_html(search.unwrap_or_default());
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Subscribers</title>
+            </head>
+            <body>
+                <h1>Subscribers</h1>
+                <form action="/admin/subscribers" method="get">
+                    <label>Email address:
+                        <input type="text" placeholder="Part of an email address" name="email" value="{search}">
+                    </label>
+                    <button type="submit">Search</button>
+                </form>
+                <p>The latest {MAX_SUBSCRIBERS_SHOWN} matching subscribers are shown.</p>
+                <table>
+                    <tr>
+                        <th>Email</th>
+                        <th>Name</th>
+                        <th>Status</th>
+                        <th>Subscribed at</th>
+                        <th></th>
+                    </tr>
+                    {subscribers_html}
+                </table>
+                <p><a href="/admin/dashboard">&lt;- Back</a></p>
+            </body>
+            </html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "Search subscribers", skip(pool))]
+async fn search_subscribers(
+    email: Option<&str>,
+    pool: &PgPool,
+) -> Result<Vec<SubscriberSummary>, anyhow::Error> {
+    let subscribers = sqlx::query_as!(
+        SubscriberSummary,
+        r#"
+        SELECT id, email, name, status, subscribed_at
+        FROM subscriptions
+        WHERE $1::text IS NULL OR strpos(lower(email), lower($1)) > 0
+        ORDER BY subscribed_at DESC
+        LIMIT $2
+        "#,
+        email,
+        MAX_SUBSCRIBERS_SHOWN
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to search subscribers.")?;
+    Ok(subscribers)
+}
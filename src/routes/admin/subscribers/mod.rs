@@ -0,0 +1,5 @@
+mod consent;
+mod get;
+
+pub use consent::{export_subscriber_consent, subscriber_consent_history};
+pub use get::subscriber_search;
@@ -0,0 +1,212 @@
+use crate::domain::{MergeFields, NewsletterTemplate};
+use crate::html_sanitizer::sanitize_html;
+use crate::utils::{e500, escape_html};
+use actix_web::{get, http::header::ContentType, web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+const ISSUES_PER_PAGE: u32 = 10;
+
+const ARCHIVE_MERGE_FIELDS: MergeFields<'static> = MergeFields {
+    name: "reader",
+    email: "",
+    unsubscribe_url: "#",
+};
+
+#[derive(serde::Deserialize)]
+pub struct ArchiveParameters {
+    page: Option<u32>,
+}
+
+struct ArchivedIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    published_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(name = "Show the newsletter archive", skip(parameters, pool))]
+#[get("/archive")]
+pub async fn newsletter_archive(
+    parameters: web::Query<ArchiveParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let page = parameters.page.unwrap_or(1).max(1);
+    let mut issues = list_archived_issues(page, &pool).await.map_err(e500)?;
+    // One more issue than fits on a page is fetched to know whether there is an older page
+    let has_older_issues = issues.len() > ISSUES_PER_PAGE as usize;
+    issues.truncate(ISSUES_PER_PAGE as usize);
+
+    let mut issues_html = String::new();
+    for issue in &issues {
+        writeln!(
+            issues_html,
+            r#"<li><a href="/archive/{}">{}</a> ({})</li>"#,
+            issue.newsletter_issue_id,
+            escape_html(&issue.title),
+            format_published_at(issue.published_at)
+        )
+        .unwrap();
+    }
+    if issues.is_empty() {
+        issues_html.push_str("<li>No issues have been published yet.</li>");
+    }
+    let mut pagination_html = String::new();
+    if page > 1 {
+        write!(
+            pagination_html,
+            r#"<a href="/archive?page={}">Newer issues</a> "#,
+            page - 1
+        )
+        .unwrap();
+    }
+    if has_older_issues {
+        write!(
+            pagination_html,
+            r#"<a href="/archive?page={}">Older issues</a>"#,
+            page + 1
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Newsletter archive</title>
+            </head>
+            <body>
+                <h1>Newsletter archive</h1>
+                <ul>
+                    {issues_html}
+                </ul>
+                <p>{pagination_html}</p>
+                <p><a href="/">Home</a></p>
+            </body>
+            </html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "Show an archived newsletter issue", skip(pool))]
+#[get("/archive/{newsletter_issue_id}")]
+pub async fn archived_newsletter_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = match get_archived_issue(newsletter_issue_id.into_inner(), &pool)
+        .await
+        .map_err(e500)?
+    {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let title = escape_html(&issue.title);
+    let published_at = format_published_at(issue.published_at);
+    let html_content = fill_in_merge_fields(&issue.html_content);
+    // Issues published before their HTML was sanitized are kept out of the page itself
+    let content_html = match sanitize_html(&html_content) {
+        Ok(html_content) => html_content,
+        Err(_) => format!(
+            r#"<iframe sandbox srcdoc="{}" width="100%" height="600"></iframe>"#,
+            escape_html(&html_content)
+        ),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>{title}</title>
+            </head>
+            <body>
+                <h1>{title}</h1>
+                <p>Published at: {published_at}</p>
+                <article>
+                    {content_html}
+                </article>
+                <p><a href="/archive">&lt;- Archive</a></p>
+            </body>
+            </html>"#,
+        )))
+}
+
+fn format_published_at(published_at: DateTime<Utc>) -> String {
+    published_at.format("%B %-d, %Y").to_string()
+}
+
+// The public copies of an issue are read by anyone, merge fields get placeholder values
+pub(crate) fn fill_in_merge_fields(html_content: &str) -> String {
+    NewsletterTemplate::parse(html_content)
+        .map(|template| template.render_html(&ARCHIVE_MERGE_FIELDS))
+        .unwrap_or_else(|_| html_content.to_owned())
+}
+
+// Scheduled issues are dated and ordered by when they went out, not when they were submitted
+#[tracing::instrument(name = "List archived newsletter issues", skip(pool))]
+async fn list_archived_issues(
+    page: u32,
+    pool: &PgPool,
+) -> Result<Vec<ArchivedIssue>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        ArchivedIssue,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            GREATEST(published_at, scheduled_for) as "published_at!"
+        FROM newsletter_issues
+        WHERE
+            published_at IS NOT NULL AND
+            in_archive AND
+            (scheduled_for IS NULL OR scheduled_for <= now())
+        ORDER BY GREATEST(published_at, scheduled_for) DESC
+        LIMIT $1 OFFSET $2
+        "#,
+        i64::from(ISSUES_PER_PAGE) + 1,
+        i64::from(page - 1) * i64::from(ISSUES_PER_PAGE)
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve archived newsletter issues.")?;
+
+    Ok(issues)
+}
+
+struct ArchivedIssueContent {
+    title: String,
+    published_at: DateTime<Utc>,
+    html_content: String,
+}
+
+#[tracing::instrument(name = "Get an archived newsletter issue", skip(pool))]
+async fn get_archived_issue(
+    newsletter_issue_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<ArchivedIssueContent>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        ArchivedIssueContent,
+        r#"
+        SELECT title, GREATEST(published_at, scheduled_for) as "published_at!", html_content
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = $1 AND
+            published_at IS NOT NULL AND
+            in_archive AND
+            (scheduled_for IS NULL OR scheduled_for <= now())
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to retrieve an archived newsletter issue.")?;
+
+    Ok(issue)
+}
@@ -0,0 +1,266 @@
+use crate::html_sanitizer::sanitize_html;
+use crate::html_to_text::html_to_text;
+use crate::routes::archive::fill_in_merge_fields;
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::{e500, escape_html};
+use actix_web::http::header::{
+    ETag, EntityTag, Header, HttpDate, IfModifiedSince, IfNoneMatch, LastModified, IF_NONE_MATCH,
+};
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, SubsecRound, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+const FEED_TITLE: &str = "Newsletter";
+const FEED_LENGTH: i64 = 20;
+
+// `published_at` is when the issue showed up in the archive, i.e. its scheduled time if it had one
+struct FeedEntry {
+    newsletter_issue_id: Uuid,
+    title: String,
+    published_at: DateTime<Utc>,
+    html_content: String,
+}
+
+#[tracing::instrument(name = "Serve the RSS feed", skip(request, pool, base_url))]
+#[get("/feed.rss")]
+pub async fn rss_feed(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let entries = get_feed_entries(&pool).await.map_err(e500)?;
+    let last_modified = get_feed_last_modified(&pool).await.map_err(e500)?;
+    let base_url = base_url.0.as_str().trim_end_matches('/');
+    Ok(feed_response(
+        &request,
+        &entries,
+        last_modified,
+        "application/rss+xml",
+        || rss(&entries, base_url),
+    ))
+}
+
+#[tracing::instrument(name = "Serve the Atom feed", skip(request, pool, base_url))]
+#[get("/feed.atom")]
+pub async fn atom_feed(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let entries = get_feed_entries(&pool).await.map_err(e500)?;
+    let last_modified = get_feed_last_modified(&pool).await.map_err(e500)?;
+    let base_url = base_url.0.as_str().trim_end_matches('/');
+    Ok(feed_response(
+        &request,
+        &entries,
+        last_modified,
+        "application/atom+xml",
+        || atom(&entries, base_url),
+    ))
+}
+
+// Feed readers poll often, they are told the feed has not changed without rendering it again.
+// `last_modified` moves whenever the entity tag does, so both validators agree on a 304.
+fn feed_response(
+    request: &HttpRequest,
+    entries: &[FeedEntry],
+    last_modified: Option<DateTime<Utc>>,
+    content_type: &str,
+    render: impl FnOnce() -> String,
+) -> HttpResponse {
+    let etag = entity_tag(entries);
+    // HTTP dates have no fractional seconds, keeping them would never match If-Modified-Since
+    let last_modified = last_modified.map(|t| HttpDate::from(SystemTime::from(t.trunc_subsecs(0))));
+    // If-Modified-Since is only looked at when If-None-Match is missing
+    let is_not_modified = if request.headers().contains_key(IF_NONE_MATCH) {
+        match IfNoneMatch::parse(request) {
+            Ok(IfNoneMatch::Any) => true,
+            Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+            Err(_) => false,
+        }
+    } else {
+        match (IfModifiedSince::parse(request), last_modified) {
+            (Ok(IfModifiedSince(since)), Some(last_modified)) => last_modified <= since,
+            _ => false,
+        }
+    };
+    let mut response = if is_not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response.insert_header(ETag(etag));
+    if let Some(last_modified) = last_modified {
+        response.insert_header(LastModified(last_modified));
+    }
+    if is_not_modified {
+        response.finish()
+    } else {
+        response
+            .content_type(format!("{}; charset=utf-8", content_type))
+            .body(render())
+    }
+}
+
+// Issues can be hidden from the archive after they are published, so the tag covers every entry
+// rather than only the latest `published_at`
+fn entity_tag(entries: &[FeedEntry]) -> EntityTag {
+    let mut hasher = Sha256::new();
+    for entry in entries {
+        hasher.update(entry.newsletter_issue_id.as_bytes());
+        hasher.update(entry.published_at.timestamp_micros().to_be_bytes());
+    }
+    EntityTag::new_strong(hex::encode(&hasher.finalize()[..16]))
+}
+
+fn rss(entries: &[FeedEntry], base_url: &str) -> String {
+    let items: Vec<rss::Item> = entries
+        .iter()
+        .map(|entry| {
+            rss::ItemBuilder::default()
+                .title(entry.title.clone())
+                .link(archive_url(base_url, entry))
+                .guid(
+                    rss::GuidBuilder::default()
+                        .value(format!("urn:uuid:{}", entry.newsletter_issue_id))
+                        .permalink(false)
+                        .build(),
+                )
+                .pub_date(entry.published_at.to_rfc2822())
+                .description(entry_content(entry))
+                .build()
+        })
+        .collect();
+    let self_link = atom_syndication::LinkBuilder::default()
+        .href(format!("{}/feed.rss", base_url))
+        .rel("self")
+        .mime_type(Some("application/rss+xml".into()))
+        .build();
+    rss::ChannelBuilder::default()
+        .namespaces(BTreeMap::from([(
+            "atom".into(),
+            rss::extension::atom::NAMESPACE.into(),
+        )]))
+        .title(FEED_TITLE)
+        .link(format!("{}/archive", base_url))
+        .description("Past issues of our newsletter")
+        .atom_ext(
+            rss::extension::atom::AtomExtensionBuilder::default()
+                .link(self_link)
+                .build(),
+        )
+        .last_build_date(entries.first().map(|entry| entry.published_at.to_rfc2822()))
+        .items(items)
+        .build()
+        .to_string()
+}
+
+fn atom(entries: &[FeedEntry], base_url: &str) -> String {
+    let feed_entries: Vec<atom_syndication::Entry> = entries
+        .iter()
+        .map(|entry| {
+            atom_syndication::EntryBuilder::default()
+                .title(entry.title.clone())
+                .link(
+                    atom_syndication::LinkBuilder::default()
+                        .href(archive_url(base_url, entry))
+                        .build(),
+                )
+                .id(format!("urn:uuid:{}", entry.newsletter_issue_id))
+                .published(Some(entry.published_at.into()))
+                .updated(entry.published_at)
+                .content(
+                    atom_syndication::ContentBuilder::default()
+                        .content_type(Some("html".into()))
+                        .value(Some(entry_content(entry)))
+                        .build(),
+                )
+                .build()
+        })
+        .collect();
+    // A feed must have an `updated` date even before the first issue is published
+    let updated = entries
+        .first()
+        .map_or(DateTime::<Utc>::UNIX_EPOCH, |entry| entry.published_at);
+    atom_syndication::FeedBuilder::default()
+        .title(FEED_TITLE)
+        .link(
+            atom_syndication::LinkBuilder::default()
+                .href(format!("{}/archive", base_url))
+                .build(),
+        )
+        .link(
+            atom_syndication::LinkBuilder::default()
+                .href(format!("{}/feed.atom", base_url))
+                .rel("self")
+                .build(),
+        )
+        .id(format!("{}/feed.atom", base_url))
+        .updated(updated)
+        .entries(feed_entries)
+        .build()
+        .to_string()
+}
+
+fn archive_url(base_url: &str, entry: &FeedEntry) -> String {
+    format!("{}/archive/{}", base_url, entry.newsletter_issue_id)
+}
+
+// Issues published before their HTML was sanitized are sent as plain text instead
+fn entry_content(entry: &FeedEntry) -> String {
+    let html_content = fill_in_merge_fields(&entry.html_content);
+    sanitize_html(&html_content)
+        .unwrap_or_else(|_| format!("<pre>{}</pre>", escape_html(&html_to_text(&html_content))))
+}
+
+#[tracing::instrument(name = "Get the feed entries", skip(pool))]
+async fn get_feed_entries(pool: &PgPool) -> Result<Vec<FeedEntry>, anyhow::Error> {
+    let entries = sqlx::query_as!(
+        FeedEntry,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            GREATEST(published_at, scheduled_for) as "published_at!",
+            html_content
+        FROM newsletter_issues
+        WHERE
+            published_at IS NOT NULL AND
+            in_archive AND
+            (scheduled_for IS NULL OR scheduled_for <= now())
+        ORDER BY 3 DESC
+        LIMIT $1
+        "#,
+        FEED_LENGTH
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to perform a query to retrieve the feed entries.")?;
+
+    Ok(entries)
+}
+
+// The last time an issue showed up in the feeds or was hidden from them. Hidden issues are kept
+// in, so taking one out of the archive is a change as well.
+#[tracing::instrument(name = "Get the last modification of the feeds", skip(pool))]
+async fn get_feed_last_modified(pool: &PgPool) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT MAX(GREATEST(published_at, scheduled_for, archive_updated_at)) as last_modified
+        FROM newsletter_issues
+        WHERE
+            published_at IS NOT NULL AND
+            (scheduled_for IS NULL OR scheduled_for <= now())
+        "#
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to perform a query to retrieve the last modification of the feeds.")?;
+
+    Ok(row.last_modified)
+}
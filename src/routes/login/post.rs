@@ -42,11 +42,11 @@ pub async fn login(
         username: form.0.username,
         password: form.0.password.clone(),
     };
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
 
     match validate_credentials(credentials, &pool).await {
         Ok(user_id) => {
-            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
             let password_feedback = Password::password_feedback(&form.0.password)
                 .map_err(LoginError::UnexpectedError)
                 .unwrap();
@@ -1,13 +1,26 @@
 mod admin;
+mod archive;
+mod feeds;
+#[allow(hidden_glob_reexports)]
 mod health_check;
+#[allow(hidden_glob_reexports)]
 mod home;
+#[allow(hidden_glob_reexports)]
 mod login;
 mod subscription_confirmation;
+mod subscription_preferences;
 mod subscriptions;
+mod tracking;
+mod unsubscription;
 
 pub use admin::*;
+pub use archive::*;
+pub use feeds::*;
 pub use health_check::*;
 pub use home::*;
 pub use login::*;
 pub use subscription_confirmation::*;
+pub use subscription_preferences::*;
 pub use subscriptions::*;
+pub use tracking::*;
+pub use unsubscription::*;
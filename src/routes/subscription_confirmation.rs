@@ -1,9 +1,15 @@
+use crate::consent::{record_consent_event, ConsentContext, ConsentEventType};
 use crate::routes::error_chain_fmt;
-use actix_web::{get, http::StatusCode, web, HttpResponse, ResponseError};
+use actix_web::http::header::ContentType;
+use actix_web::{get, http::StatusCode, web, HttpRequest, HttpResponse, ResponseError};
 use anyhow::Context;
-use sqlx::PgPool;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+// Subscribers who miss this window ask for a new link to be sent to them
+pub(crate) const CONFIRMATION_TOKEN_TTL: Duration = Duration::hours(24);
+
 #[derive(serde::Deserialize)]
 pub struct Parameters {
     subscription_token: String,
@@ -13,6 +19,10 @@ pub struct Parameters {
 pub enum ConfirmationError {
     #[error("There is no subscriber associated with the provided token.")]
     UnknownToken,
+    #[error("This confirmation link has already been used.")]
+    UsedToken,
+    #[error("This confirmation link has expired, ask for a new one to be sent to you.")]
+    ExpiredToken,
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -25,15 +35,56 @@ pub enum DatabaseError {
         "A database error encountered while trying to update the subscriber's status to `confirmed`."
     )]
     ConfirmSubscriberError(#[source] sqlx::Error),
+    #[error("A database error encountered while trying to mark subscription tokens as consumed.")]
+    ConsumeTokensError(#[source] sqlx::Error),
 }
 
 impl ResponseError for ConfirmationError {
     fn status_code(&self) -> reqwest::StatusCode {
         match self {
             ConfirmationError::UnknownToken => StatusCode::UNAUTHORIZED,
+            ConfirmationError::UsedToken | ConfirmationError::ExpiredToken => StatusCode::GONE,
             ConfirmationError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    // Subscribers reach this endpoint from their mail client, they get a page rather than text
+    fn error_response(&self) -> HttpResponse {
+        let (title, message_html) = match self {
+            ConfirmationError::UnknownToken => (
+                "Invalid confirmation link",
+                "<p>This confirmation link is not valid. Check that the whole link from the email \
+                was opened.</p>"
+                    .to_owned(),
+            ),
+            ConfirmationError::UsedToken => (
+                "Confirmation link already used",
+                r#"<p>This confirmation link has already been used.</p>
+                <p><a href="/subscriptions">Subscribe again</a></p>"#
+                    .to_owned(),
+            ),
+            ConfirmationError::ExpiredToken => (
+                "Confirmation link expired",
+                format!(
+                    r#"<p>This confirmation link has expired, links are valid for {} hours.</p>
+                <form action="/subscriptions/resend-confirmation" method="post">
+                    <label>Email address:
+                        <input type="email" placeholder="Enter your email address" name="email">
+                    </label>
+                    <button type="submit">Send me a new link</button>
+                </form>"#,
+                    CONFIRMATION_TOKEN_TTL.num_hours()
+                ),
+            ),
+            ConfirmationError::UnexpectedError(_) => (
+                "Something went wrong",
+                "<p>We could not confirm your subscription, please try again later.</p>".to_owned(),
+            ),
+        };
+        HttpResponse::build(self.status_code())
+            .content_type(ContentType::html())
+            .body(confirmation_page(title, &message_html))
+    }
 }
 
 impl std::fmt::Debug for ConfirmationError {
@@ -47,24 +98,99 @@ impl std::fmt::Debug for DatabaseError {
     }
 }
 
+struct SubscriptionToken {
+    subscriber_id: Uuid,
+    created_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+    subscriber_status: String,
+    subscriber_email: String,
+}
+
 #[get("/subscriptions/confirm")]
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters))]
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, request))]
 pub async fn confirm(
     parameters: web::Query<Parameters>,
     pool: web::Data<PgPool>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, ConfirmationError> {
-    let subscriber_id = get_subscriber_id_from_token(&parameters.subscription_token, &pool)
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let token = get_token(&parameters.subscription_token, &mut transaction)
         .await
         .context("Failed to fetch subscriber id from the database with the provided token.")?
         .ok_or(ConfirmationError::UnknownToken)?;
-    let _ = confirm_subscriber(subscriber_id, &pool)
+    // Following the link again, or a link sent before the subscriber confirmed, changes nothing
+    if token.subscriber_status == "confirmed" {
+        return Ok(confirmation_response(
+            "Already confirmed",
+            "<p>Your subscription was already confirmed, there is nothing left to do.</p>",
+        ));
+    }
+    if token.consumed_at.is_some() {
+        return Err(ConfirmationError::UsedToken);
+    }
+    if token.created_at + CONFIRMATION_TOKEN_TTL < Utc::now() {
+        return Err(ConfirmationError::ExpiredToken);
+    }
+    confirm_subscriber(token.subscriber_id, &mut transaction)
         .await
-        .context("Failed to update subscriber status to `confirmed`.");
-    Ok(HttpResponse::Ok().finish())
+        .context("Failed to update subscriber status to `confirmed`.")?;
+    consume_tokens(token.subscriber_id, &mut transaction)
+        .await
+        .context("Failed to mark the subscription tokens as consumed.")?;
+    record_consent_event(
+        &mut transaction,
+        token.subscriber_id,
+        ConsentEventType::Confirmation,
+        &token.subscriber_email,
+        &ConsentContext::from_request(&request),
+        None,
+    )
+    .await
+    .context("Failed to record the consent of the subscriber.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber.")?;
+    Ok(confirmation_response(
+        "Subscription confirmed",
+        "<p>Thanks for confirming your email address, you will receive our next issue.</p>",
+    ))
+}
+
+fn confirmation_response(title: &str, message_html: &str) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(confirmation_page(title, message_html))
+}
+
+fn confirmation_page(title: &str, message_html: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+        <html lang="en">
+        <head>
+            <meta http-equiv="content-type" content="text/html; charset=utf-8">
+            <title>{title}</title>
+        </head>
+        <body>
+            <h1>{title}</h1>
+            {message_html}
+            <p><a href="/archive">Read past issues</a> | <a href="/">Home</a></p>
+        </body>
+        </html>"#,
+    )
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-async fn confirm_subscriber(subscriber_id: Uuid, pool: &PgPool) -> Result<(), DatabaseError> {
+#[tracing::instrument(
+    name = "Mark subscriber as confirmed",
+    skip(subscriber_id, transaction)
+)]
+async fn confirm_subscriber(
+    subscriber_id: Uuid,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), DatabaseError> {
     sqlx::query!(
         r#"
         UPDATE subscriptions SET status = 'confirmed'
@@ -72,28 +198,58 @@ async fn confirm_subscriber(subscriber_id: Uuid, pool: &PgPool) -> Result<(), Da
         "#,
         subscriber_id
     )
-    .execute(pool)
+    .execute(transaction)
     .await
     .map_err(DatabaseError::ConfirmSubscriberError)?;
 
     Ok(())
 }
 
-#[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
-async fn get_subscriber_id_from_token(
+// Every link sent to the subscriber stops working once one of them is followed
+#[tracing::instrument(name = "Consume subscription tokens", skip(transaction))]
+async fn consume_tokens(
+    subscriber_id: Uuid,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), DatabaseError> {
+    sqlx::query!(
+        r#"
+        UPDATE subscription_tokens SET consumed_at = now()
+        WHERE subscriber_id = $1 AND consumed_at IS NULL
+        "#,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await
+    .map_err(DatabaseError::ConsumeTokensError)?;
+
+    Ok(())
+}
+
+// The row stays locked until the transaction ends, two clicks on the same link cannot both succeed
+#[tracing::instrument(name = "Get subscription token", skip(subscription_token, transaction))]
+async fn get_token(
     subscription_token: &str,
-    pool: &PgPool,
-) -> Result<Option<Uuid>, DatabaseError> {
-    let result = sqlx::query!(
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<SubscriptionToken>, DatabaseError> {
+    let token = sqlx::query_as!(
+        SubscriptionToken,
         r#"
-        SELECT subscriber_id FROM subscription_tokens
-        WHERE subscription_token = $1
+        SELECT
+            t.subscriber_id,
+            t.created_at,
+            t.consumed_at,
+            s.status as subscriber_status,
+            s.email as subscriber_email
+        FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE t.subscription_token = $1
+        FOR UPDATE
         "#,
         subscription_token
     )
-    .fetch_optional(pool)
+    .fetch_optional(transaction)
     .await
     .map_err(DatabaseError::GetSubscriberError)?;
 
-    Ok(result.map(|r| r.subscriber_id))
+    Ok(token)
 }
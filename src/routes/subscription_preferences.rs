@@ -0,0 +1,461 @@
+use crate::consent::{record_consent_event, ConsentContext, ConsentEventType};
+use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::email_outbox_worker::enqueue_email;
+use crate::link_signer::LinkSigner;
+use crate::routes::admin::{get_subscriber_lists, list_checkboxes_html};
+use crate::routes::error_chain_fmt;
+use crate::utils::{escape_html, see_other};
+use actix_web::http::header::ContentType;
+use actix_web::{get, http::StatusCode, post, web, HttpRequest, HttpResponse, ResponseError};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use actix_web_lab::extract::UrlEncodedForm;
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::fmt::Write;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct PreferencesParameters {
+    token: String,
+}
+
+// Checkboxes submit one `list_id` pair per list the subscriber wants to receive
+#[derive(serde::Deserialize)]
+pub struct PreferencesFormData {
+    name: String,
+    email: String,
+    #[serde(default)]
+    list_id: Vec<Uuid>,
+}
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("The preferences link is invalid.")]
+    InvalidToken(#[source] anyhow::Error),
+    #[error("There is no confirmed subscription for this link.")]
+    UnknownSubscriber,
+    #[error("This email change link has already been used or a newer change was requested.")]
+    UnknownEmailChange,
+    #[error("The new email address is already subscribed.")]
+    EmailTaken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for PreferencesError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PreferencesError::InvalidToken(_) | PreferencesError::UnknownEmailChange => {
+                StatusCode::UNAUTHORIZED
+            }
+            PreferencesError::UnknownSubscriber => StatusCode::NOT_FOUND,
+            PreferencesError::EmailTaken => StatusCode::CONFLICT,
+            PreferencesError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+struct Preferences {
+    name: String,
+    email: String,
+    pending_email: Option<String>,
+}
+
+#[get("/subscriptions/preferences")]
+#[tracing::instrument(
+    name = "Subscriber preferences form",
+    skip(parameters, flash_messages, pool, link_signer)
+)]
+pub async fn preferences_form(
+    parameters: web::Query<PreferencesParameters>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+    link_signer: web::Data<LinkSigner>,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = link_signer
+        .verify_preferences_token(&parameters.token)
+        .map_err(PreferencesError::InvalidToken)?;
+    let preferences = get_preferences(subscriber_id, &pool)
+        .await
+        .context("Failed to fetch the subscriber preferences.")?
+        .ok_or(PreferencesError::UnknownSubscriber)?;
+    let lists = get_subscriber_lists(&pool).await?;
+    let memberships = get_memberships(subscriber_id, &pool)
+        .await
+        .context("Failed to fetch the lists of a subscriber.")?;
+
+    let mut message_html = String::new();
+    for message in flash_messages.iter() {
+        writeln!(message_html, "<p><i>{}</i></p>", message.content()).unwrap();
+    }
+    let pending_email_html = preferences
+        .pending_email
+        .map(|pending_email| {
+            format!(
+                "<p>Follow the link we sent to {} to start receiving the newsletter there.</p>",
+                escape_html(&pending_email)
+            )
+        })
+        .unwrap_or_default();
+    let lists_html = if lists.is_empty() {
+        String::new()
+    } else {
+        format!(
+            r#"<fieldset>
+                        <legend>Lists you receive:</legend>
+                        {}
+                    </fieldset>"#,
+            list_checkboxes_html(&lists, &memberships)
+        )
+    };
+    // A verified token only contains a uuid and a hex tag, both safe to put in a query string
+    let token = escape_html(&parameters.token);
+    let name = escape_html(&preferences.name);
+    let email = escape_html(&preferences.email);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Your preferences</title>
+            </head>
+            <body>
+                {message_html}
+                <h1>Your preferences</h1>
+                <form action="/subscriptions/preferences?token={token}" method="post">
+                    <label>Name:
+                        <input type="text" name="name" value="{name}">
+                    </label>
+                    <br>
+                    <label>Email address (a new address has to be confirmed first):
+                        <input type="email" name="email" value="{email}">
+                    </label>
+                    {pending_email_html}
+                    <br>
+                    {lists_html}
+                    <button type="submit">Save preferences</button>
+                </form>
+            </body>
+            </html>"#,
+        )))
+}
+
+#[post("/subscriptions/preferences")]
+#[tracing::instrument(
+    name = "Update subscriber preferences",
+    skip(parameters, form, pool, link_signer)
+)]
+pub async fn update_preferences(
+    parameters: web::Query<PreferencesParameters>,
+    form: UrlEncodedForm<PreferencesFormData>,
+    pool: web::Data<PgPool>,
+    link_signer: web::Data<LinkSigner>,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = link_signer
+        .verify_preferences_token(&parameters.token)
+        .map_err(PreferencesError::InvalidToken)?;
+    let preferences = get_preferences(subscriber_id, &pool)
+        .await
+        .context("Failed to fetch the subscriber preferences.")?
+        .ok_or(PreferencesError::UnknownSubscriber)?;
+    let preferences_page = format!("/subscriptions/preferences?token={}", parameters.token);
+    let UrlEncodedForm(form) = form;
+
+    // Flash messages are rendered as is, the submitted values they contain must be escaped
+    let name = match SubscriberName::parse(form.name) {
+        Ok(name) => name,
+        Err(e) => {
+            FlashMessage::error(escape_html(&e)).send();
+            return Ok(see_other(&preferences_page));
+        }
+    };
+    let new_email = form.email.trim();
+    let new_email = if new_email == preferences.email {
+        None
+    } else {
+        match SubscriberEmail::parse(new_email.to_owned()) {
+            Ok(new_email) => Some(new_email),
+            Err(e) => {
+                FlashMessage::error(escape_html(&e)).send();
+                return Ok(see_other(&preferences_page));
+            }
+        }
+    };
+    if let Some(new_email) = &new_email {
+        let is_taken = is_email_subscribed(new_email, &pool)
+            .await
+            .context("Failed to check whether an email address is subscribed.")?;
+        if is_taken {
+            FlashMessage::error(format!(
+                "{} is already subscribed.",
+                escape_html(new_email.as_ref())
+            ))
+            .send();
+            return Ok(see_other(&preferences_page));
+        }
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    save_preferences(
+        &mut transaction,
+        subscriber_id,
+        &name,
+        new_email.as_ref(),
+        &form.list_id,
+    )
+    .await
+    .context("Failed to save the subscriber preferences.")?;
+    if let Some(new_email) = &new_email {
+        enqueue_email_change_confirmation(&mut transaction, &link_signer, subscriber_id, new_email)
+            .await
+            .context("Failed to store an email change confirmation in the outbox.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to save the subscriber preferences.")?;
+
+    FlashMessage::info("Your preferences have been saved.").send();
+    if let Some(new_email) = new_email {
+        FlashMessage::info(format!(
+            "We sent a confirmation link to {}, your email address changes once you follow it.",
+            escape_html(new_email.as_ref())
+        ))
+        .send();
+    }
+    Ok(see_other(&preferences_page))
+}
+
+#[get("/subscriptions/preferences/email")]
+#[tracing::instrument(
+    name = "Confirm an email change",
+    skip(parameters, pool, link_signer, request)
+)]
+pub async fn confirm_email_change(
+    parameters: web::Query<PreferencesParameters>,
+    pool: web::Data<PgPool>,
+    link_signer: web::Data<LinkSigner>,
+    request: HttpRequest,
+) -> Result<HttpResponse, PreferencesError> {
+    let (subscriber_id, new_email) = link_signer
+        .verify_email_change_token(&parameters.token)
+        .map_err(PreferencesError::InvalidToken)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let is_changed = match change_email(subscriber_id, &new_email, &mut transaction).await {
+        Ok(is_changed) => is_changed,
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some("23505") => {
+            return Err(PreferencesError::EmailTaken)
+        }
+        Err(e) => {
+            return Err(anyhow::Error::new(e)
+                .context("Failed to change an email.")
+                .into())
+        }
+    };
+    if !is_changed {
+        return Err(PreferencesError::UnknownEmailChange);
+    }
+    // Following the link is the new address' consent to receive the newsletter
+    record_consent_event(
+        &mut transaction,
+        subscriber_id,
+        ConsentEventType::EmailChange,
+        &new_email,
+        &ConsentContext::from_request(&request),
+        None,
+    )
+    .await
+    .context("Failed to record the consent of the new email address.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change an email.")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Email address changed</title>
+            </head>
+            <body>
+                <p>Your email address has been changed to {}.</p>
+            </body>
+            </html>"#,
+            escape_html(&new_email)
+        )))
+}
+
+#[tracing::instrument(
+    name = "Add an email change confirmation to the outbox",
+    skip(transaction, link_signer, new_email)
+)]
+async fn enqueue_email_change_confirmation(
+    transaction: &mut Transaction<'_, Postgres>,
+    link_signer: &LinkSigner,
+    subscriber_id: Uuid,
+    new_email: &SubscriberEmail,
+) -> Result<(), sqlx::Error> {
+    let confirmation_link = link_signer.email_change_url(subscriber_id, new_email.as_ref());
+    let html_body = format!(
+        "Click <a href=\"{}\">here</a> to receive our newsletter at this address.",
+        confirmation_link
+    );
+    let text_body = format!(
+        "Visit {} to receive our newsletter at this address.",
+        confirmation_link
+    );
+    enqueue_email(
+        transaction,
+        new_email,
+        "Confirm your new email address",
+        &html_body,
+        &text_body,
+    )
+    .await
+}
+
+#[tracing::instrument(name = "Get subscriber preferences", skip(pool))]
+async fn get_preferences(
+    subscriber_id: Uuid,
+    pool: &PgPool,
+) -> Result<Option<Preferences>, sqlx::Error> {
+    sqlx::query_as!(
+        Preferences,
+        r#"
+        SELECT name, email, pending_email
+        FROM subscriptions
+        WHERE id = $1 AND status = 'confirmed'
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[tracing::instrument(name = "Get the lists of a subscriber", skip(pool))]
+async fn get_memberships(subscriber_id: Uuid, pool: &PgPool) -> Result<Vec<Uuid>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT list_id FROM subscriptions_lists WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.list_id).collect())
+}
+
+#[tracing::instrument(name = "Check whether an email is subscribed", skip(email, pool))]
+async fn is_email_subscribed(email: &SubscriberEmail, pool: &PgPool) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT EXISTS (SELECT 1 FROM subscriptions WHERE email = $1) as "is_subscribed!""#,
+        email.as_ref()
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.is_subscribed)
+}
+
+// Lists that were deleted since the form was rendered are skipped
+#[tracing::instrument(
+    name = "Save subscriber preferences",
+    skip(transaction, name, new_email, list_ids)
+)]
+async fn save_preferences(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    name: &SubscriberName,
+    new_email: Option<&SubscriberEmail>,
+    list_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET name = $2, pending_email = COALESCE($3, pending_email)
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        name.as_ref(),
+        new_email.map(|e| e.as_ref())
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM subscriptions_lists WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions_lists (subscriber_id, list_id)
+        SELECT $1, list_id
+        FROM lists
+        WHERE list_id = ANY($2)
+        "#,
+        subscriber_id,
+        list_ids
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(())
+}
+
+// Only the latest requested address can be confirmed, older links stop working.
+// Deliveries are keyed by address: the pending and failed ones move to the new address, otherwise
+// the worker could not match them to the subscriber anymore.
+#[tracing::instrument(name = "Change a subscriber email", skip(new_email, transaction))]
+async fn change_email(
+    subscriber_id: Uuid,
+    new_email: &str,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<bool, sqlx::Error> {
+    let changed = sqlx::query!(
+        r#"
+        UPDATE subscriptions s
+        SET email = s.pending_email, pending_email = NULL
+        FROM (SELECT id, email FROM subscriptions WHERE id = $1 FOR UPDATE) old
+        WHERE s.id = old.id AND s.pending_email = $2 AND s.status = 'confirmed'
+        RETURNING old.email AS old_email
+        "#,
+        subscriber_id,
+        new_email
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let old_email = match changed {
+        Some(changed) => changed.old_email,
+        None => return Ok(false),
+    };
+    sqlx::query!(
+        "UPDATE issue_delivery_queue SET subscriber_email = $2 WHERE subscriber_email = $1",
+        old_email,
+        new_email
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        "UPDATE issue_delivery_failures SET subscriber_email = $2 WHERE subscriber_email = $1",
+        old_email,
+        new_email
+    )
+    .execute(&mut *transaction)
+    .await?;
+    Ok(true)
+}
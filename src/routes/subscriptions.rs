@@ -1,18 +1,36 @@
+use crate::consent::{record_consent_event, ConsentContext, ConsentEventType};
 use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
-use crate::email_client::EmailClient;
+use crate::email_outbox_worker::enqueue_email;
+use crate::routes::admin::{get_subscriber_lists, list_checkboxes_html};
 use crate::startup::ApplicationBaseUrl;
-use actix_web::{http::StatusCode, post, web, HttpResponse, ResponseError};
+use crate::utils::{e500, escape_html};
+use actix_web::http::header::ContentType;
+use actix_web::{get, http::StatusCode, post, web, HttpRequest, HttpResponse, ResponseError};
+use actix_web_lab::extract::UrlEncodedForm;
 use anyhow::Context;
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+// The shortest wait between two confirmation emails sent to the same address
+const RESEND_CONFIRMATION_INTERVAL: Duration = Duration::minutes(5);
+
+// Checkboxes submit one `list_id` pair per list the subscriber wants to join
 #[derive(serde::Deserialize)]
 pub struct FormData {
     name: String,
     email: String,
+    #[serde(default)]
+    list_id: Vec<Uuid>,
+    // Which of our forms the signup came from, kept as part of the consent record
+    source: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResendConfirmationFormData {
+    email: String,
 }
 
 // FormData impl's
@@ -34,12 +52,22 @@ pub enum SubscribeError {
     UnexpectedError(#[from] anyhow::Error),
 }
 
+#[derive(thiserror::Error)]
+pub enum ResendConfirmationError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
 #[derive(thiserror::Error)]
 pub enum InsertDatabaseError {
     #[error("A database error was encountered while trying to store a subscription token.")]
     StoreTokenError(#[source] sqlx::Error),
     #[error("A database error was encountered while trying to insert a new subscriber.")]
     InsertSubscriberError(#[source] sqlx::Error),
+    #[error("A database error was encountered while trying to add a new subscriber to lists.")]
+    JoinListsError(#[source] sqlx::Error),
 }
 
 // SubscribeError impl's
@@ -64,6 +92,22 @@ impl From<String> for SubscribeError {
     }
 }
 
+// ResendConfirmationError impl's
+impl ResponseError for ResendConfirmationError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ResendConfirmationError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ResendConfirmationError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl std::fmt::Debug for ResendConfirmationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
 //InsertDatabaseError impl's
 impl std::fmt::Debug for InsertDatabaseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -71,62 +115,267 @@ impl std::fmt::Debug for InsertDatabaseError {
     }
 }
 
+#[get("/subscriptions")]
+pub async fn subscribe_form(pool: web::Data<PgPool>) -> Result<HttpResponse, actix_web::Error> {
+    let lists = get_subscriber_lists(&pool).await.map_err(e500)?;
+    let lists_html = if lists.is_empty() {
+        String::new()
+    } else {
+        format!(
+            r#"<fieldset>
+                        <legend>Lists to join:</legend>
+                        {}
+                    </fieldset>"#,
+            list_checkboxes_html(&lists, &[])
+        )
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Subscribe</title>
+            </head>
+            <body>
+                <form action="/subscriptions" method="post">
+                    <label>Name:
+                        <input type="text" placeholder="Enter your name" name="name">
+                    </label>
+                    <br>
+                    <label>Email address:
+                        <input type="email" placeholder="Enter your email address" name="email">
+                    </label>
+                    <br>
+                    {lists_html}
+                    <input type="hidden" name="source" value="subscribe_form">
+                    <button type="submit">Subscribe</button>
+                </form>
+                <p><a href="/">&lt;- Home</a></p>
+            </body>
+            </html>"#,
+        )))
+}
+
 #[post("/subscriptions")]
 #[tracing::instrument(
     name = "Adding a new subscriber.",
-    skip(form, pool, email_client, base_url),
+    skip(form, pool, base_url, request),
     fields(
         subscriber_email = %form.email,
         subscriber_name = %form.name
     )
 )]
 async fn subscribe(
-    form: web::Form<FormData>,
+    form: UrlEncodedForm<FormData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into()?;
+    let UrlEncodedForm(mut form) = form;
+    let source = form.source.take();
+    let mut list_ids = std::mem::take(&mut form.list_id);
+    list_ids.sort();
+    list_ids.dedup();
+    let new_subscriber = form.try_into()?;
 
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postges connection from the pool.")?;
-    let subscriber_id = insert_subscriber(&new_subscriber, &mut transaction)
+    let subscriber_id = match insert_subscriber(&new_subscriber, &mut transaction)
         .await
-        .context("Failed to insert new subscriber in the database.")?;
+        .context("Failed to insert new subscriber in the database.")?
+    {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            let existing = get_subscriber_by_email(&new_subscriber.email, &mut transaction)
+                .await
+                .context("Failed to look up the existing subscriber.")?;
+            match existing.status.as_str() {
+                // Confirmed subscribers get the same answer as a new signup and no email, the
+                // endpoint does not tell who subscribed
+                "confirmed" => return Ok(HttpResponse::Ok().finish()),
+                // The signup form doubles as a resend form, with the same rate limit
+                "pending_confirmation" => {
+                    let last_sent_at = get_last_token_created_at(existing.id, &mut transaction)
+                        .await
+                        .context("Failed to look up when the last confirmation email was sent.")?;
+                    if was_sent_recently(last_sent_at) {
+                        return Ok(HttpResponse::Ok().finish());
+                    }
+                }
+                // Unsubscribed subscribers opt in again by confirming their address again
+                _ => {}
+            }
+            // The latest signup wins, its name and lists replace the previous ones
+            resubscribe(existing.id, &new_subscriber, &mut transaction)
+                .await
+                .context("Failed to mark the existing subscriber as pending confirmation.")?;
+            existing.id
+        }
+    };
+    record_consent_event(
+        &mut transaction,
+        subscriber_id,
+        ConsentEventType::Signup,
+        new_subscriber.email.as_ref(),
+        &ConsentContext::from_request(&request),
+        source.as_deref(),
+    )
+    .await
+    .context("Failed to record the consent of the new subscriber.")?;
+    let n_joined = join_lists(subscriber_id, &list_ids, &mut transaction)
+        .await
+        .context("Failed to add the new subscriber to lists.")?;
+    if n_joined != list_ids.len() as u64 {
+        return Err(SubscribeError::ValidationError(
+            "Some of the selected lists do not exist.".into(),
+        ));
+    }
     let subscriber_token = generate_subscriber_token();
 
     store_token(subscriber_id, &subscriber_token, &mut transaction)
         .await
         .context("Failed to store the confirmation token for a new subscriber")?;
+    // Sent by the background worker, the signup goes through even if the email provider is down
+    enqueue_confirmation_email(
+        &mut transaction,
+        &new_subscriber.email,
+        &base_url.0,
+        &subscriber_token,
+    )
+    .await
+    .context("Failed to add the confirmation email to the outbox.")?;
 
     transaction
         .commit()
         .await
         .context("Failed to commit SQL transaction to store a new subsciber.")?;
+    Ok(HttpResponse::Ok().finish())
+}
 
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
-        &subscriber_token,
+#[post("/subscriptions/resend-confirmation")]
+#[tracing::instrument(
+    name = "Resending a confirmation email.",
+    skip(form, pool, base_url),
+    fields(subscriber_email = %form.email)
+)]
+pub async fn resend_confirmation(
+    form: web::Form<ResendConfirmationFormData>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, ResendConfirmationError> {
+    let email =
+        SubscriberEmail::parse(form.0.email).map_err(ResendConfirmationError::ValidationError)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    // Unknown and already confirmed addresses get the same answer, nobody learns who subscribed
+    let subscriber_id = match get_pending_subscriber_id(&email, &mut transaction)
+        .await
+        .context("Failed to look up the pending subscriber.")?
+    {
+        Some(subscriber_id) => subscriber_id,
+        None => return Ok(resent_confirmation_page(&email)),
+    };
+    let last_sent_at = get_last_token_created_at(subscriber_id, &mut transaction)
+        .await
+        .context("Failed to look up when the last confirmation email was sent.")?;
+    // Answering differently here would tell who is waiting to be confirmed
+    if was_sent_recently(last_sent_at) {
+        tracing::info!("A confirmation email was sent recently, not sending another one.");
+        return Ok(resent_confirmation_page(&email));
+    }
+    // Links sent before keep working until they expire, the emails may simply be late
+    let subscriber_token = generate_subscriber_token();
+    store_token(subscriber_id, &subscriber_token, &mut transaction)
+        .await
+        .context("Failed to store a new confirmation token.")?;
+    enqueue_confirmation_email(&mut transaction, &email, &base_url.0, &subscriber_token)
+        .await
+        .context("Failed to add the confirmation email to the outbox.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new confirmation token.")?;
+    Ok(resent_confirmation_page(&email))
+}
+
+// The expired confirmation link page posts here, so the answer is a page too
+fn resent_confirmation_page(email: &SubscriberEmail) -> HttpResponse {
+    let email = escape_html(email.as_ref());
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Confirmation link sent</title>
+            </head>
+            <body>
+                <p>If {email} is waiting to be confirmed, a new confirmation link is on its way.</p>
+                <p><a href="/">&lt;- Home</a></p>
+            </body>
+            </html>"#,
+        ))
+}
+
+// The row stays locked until the transaction ends so concurrent requests are rate limited too
+#[tracing::instrument(name = "Get a pending subscriber by email.", skip(transaction))]
+async fn get_pending_subscriber_id(
+    email: &SubscriberEmail,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions
+        WHERE email = $1 AND status = 'pending_confirmation'
+        FOR UPDATE
+        "#,
+        email.as_ref()
     )
-    .await
-    .context("Failed to send a confirmation email.")?;
-    Ok(HttpResponse::Ok().finish())
+    .fetch_optional(transaction)
+    .await?;
+    Ok(subscriber.map(|s| s.id))
+}
+
+fn was_sent_recently(last_sent_at: Option<DateTime<Utc>>) -> bool {
+    last_sent_at.is_some_and(|sent_at| sent_at + RESEND_CONFIRMATION_INTERVAL > Utc::now())
+}
+
+#[tracing::instrument(name = "Get the latest subscription token date.", skip(transaction))]
+async fn get_last_token_created_at(
+    subscriber_id: Uuid,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let last_token = sqlx::query!(
+        r#"
+        SELECT MAX(created_at) as created_at FROM subscription_tokens
+        WHERE subscriber_id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_one(transaction)
+    .await?;
+    Ok(last_token.created_at)
 }
 
 #[tracing::instrument(
-    name = "",
-    skip(email_client, new_subscriber, base_url, subscription_token)
+    name = "Add a confirmation email to the outbox.",
+    skip(transaction, email, base_url, subscription_token)
 )]
-async fn send_confirmation_email(
-    email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+async fn enqueue_confirmation_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
     base_url: &reqwest::Url,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), sqlx::Error> {
     let path = &format!(
         "/subscriptions/confirm?subscription_token={}",
         subscription_token
@@ -141,11 +390,10 @@ async fn send_confirmation_email(
         confirmation_link
     );
 
-    email_client
-        .send_email(&new_subscriber.email, "Welcome!", &html_body, &text_body)
-        .await
+    enqueue_email(transaction, email, "Welcome!", &html_body, &text_body).await
 }
 
+// Returns `None` when the email address is already known
 #[tracing::instrument(
     name = "Saving the new subscriber to the database.",
     skip(new_subscriber, transaction)
@@ -153,13 +401,14 @@ async fn send_confirmation_email(
 async fn insert_subscriber(
     new_subscriber: &NewSubscriber,
     transaction: &mut Transaction<'_, Postgres>,
-) -> Result<Uuid, InsertDatabaseError> {
+) -> Result<Option<Uuid>, InsertDatabaseError> {
     let subscriber_id = Uuid::new_v4();
 
-    sqlx::query!(
+    let n_inserted = sqlx::query!(
         r#"
         INSERT INTO  subscriptions (id, email, name,subscribed_at, status)
         VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        ON CONFLICT (email) DO NOTHING
         "#,
         subscriber_id,
         &new_subscriber.email.as_ref(),
@@ -168,8 +417,88 @@ async fn insert_subscriber(
     )
     .execute(transaction)
     .await
+    .map_err(InsertDatabaseError::InsertSubscriberError)?
+    .rows_affected();
+    Ok((n_inserted == 1).then_some(subscriber_id))
+}
+
+struct ExistingSubscriber {
+    id: Uuid,
+    status: String,
+}
+
+// The row stays locked until the transaction ends so concurrent signups are handled one by one
+#[tracing::instrument(name = "Get an existing subscriber by email.", skip(transaction))]
+async fn get_subscriber_by_email(
+    email: &SubscriberEmail,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<ExistingSubscriber, sqlx::Error> {
+    sqlx::query_as!(
+        ExistingSubscriber,
+        r#"
+        SELECT id, status FROM subscriptions
+        WHERE email = $1
+        FOR UPDATE
+        "#,
+        email.as_ref()
+    )
+    .fetch_one(transaction)
+    .await
+}
+
+#[tracing::instrument(
+    name = "Saving the returning subscriber to the database.",
+    skip(new_subscriber, transaction)
+)]
+async fn resubscribe(
+    subscriber_id: Uuid,
+    new_subscriber: &NewSubscriber,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), InsertDatabaseError> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET name = $2, status = 'pending_confirmation'
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        new_subscriber.name.as_ref()
+    )
+    .execute(&mut *transaction)
+    .await
     .map_err(InsertDatabaseError::InsertSubscriberError)?;
-    Ok(subscriber_id)
+    sqlx::query!(
+        "DELETE FROM subscriptions_lists WHERE subscriber_id = $1",
+        subscriber_id
+    )
+    .execute(transaction)
+    .await
+    .map_err(InsertDatabaseError::JoinListsError)?;
+    Ok(())
+}
+
+// Returns how many of the lists were joined, unknown list ids are skipped
+#[tracing::instrument(name = "Adding the new subscriber to lists.", skip(transaction))]
+async fn join_lists(
+    subscriber_id: Uuid,
+    list_ids: &[Uuid],
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<u64, InsertDatabaseError> {
+    let n_joined = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions_lists (subscriber_id, list_id)
+        SELECT $1, list_id
+        FROM lists
+        WHERE list_id = ANY($2)
+        "#,
+        subscriber_id,
+        list_ids
+    )
+    .execute(transaction)
+    .await
+    .map_err(InsertDatabaseError::JoinListsError)?
+    .rows_affected();
+    Ok(n_joined)
 }
 
 #[tracing::instrument(
@@ -0,0 +1,119 @@
+use crate::link_signer::LinkSigner;
+use crate::utils::see_other;
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::{get, web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+// The smallest transparent 1x1 GIF
+const TRACKING_PIXEL: [u8; 43] = [
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(Debug)]
+enum EventType {
+    Open,
+    Click,
+}
+
+impl EventType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EventType::Open => "open",
+            EventType::Click => "click",
+        }
+    }
+}
+
+// Readers are always sent on to the destination, failing to record the click only costs us a stat
+#[get("/t/{token}")]
+#[tracing::instrument(name = "Track a newsletter click", skip(token, link_signer, pool))]
+pub async fn track_click(
+    token: web::Path<String>,
+    link_signer: web::Data<LinkSigner>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let (newsletter_issue_id, subscriber_id, destination) =
+        match link_signer.verify_click_token(&token) {
+            Ok(click) => click,
+            Err(_) => return HttpResponse::NotFound().finish(),
+        };
+    record_event(
+        &pool,
+        newsletter_issue_id,
+        subscriber_id,
+        EventType::Click,
+        Some(destination.as_str()),
+    )
+    .await;
+    see_other(destination.as_str())
+}
+
+#[get("/t/{token}/open.gif")]
+#[tracing::instrument(name = "Track a newsletter open", skip(token, link_signer, pool))]
+pub async fn track_open(
+    token: web::Path<String>,
+    link_signer: web::Data<LinkSigner>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    let (newsletter_issue_id, subscriber_id) = match link_signer.verify_open_token(&token) {
+        Ok(open) => open,
+        Err(_) => return HttpResponse::NotFound().finish(),
+    };
+    record_event(
+        &pool,
+        newsletter_issue_id,
+        subscriber_id,
+        EventType::Open,
+        None,
+    )
+    .await;
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        // Every time the issue is opened again the pixel has to be fetched again
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(TRACKING_PIXEL.as_slice())
+}
+
+// Events of subscribers or issues that have been deleted since the email was sent are dropped
+#[tracing::instrument(name = "Record a newsletter event", skip(pool, url))]
+async fn record_event(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    event_type: EventType,
+    url: Option<&str>,
+) {
+    let outcome = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_events (
+            newsletter_event_id,
+            newsletter_issue_id,
+            subscriber_id,
+            event_type,
+            url,
+            occurred_at
+        )
+        SELECT $1, i.newsletter_issue_id, s.id, $4, $5, now()
+        FROM newsletter_issues i, subscriptions s
+        WHERE i.newsletter_issue_id = $2 AND s.id = $3
+        "#,
+        Uuid::new_v4(),
+        newsletter_issue_id,
+        subscriber_id,
+        event_type.as_str(),
+        url
+    )
+    .execute(pool)
+    .await;
+    if let Err(e) = outcome {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to record a newsletter {} event.",
+            event_type.as_str()
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use crate::consent::{record_consent_event, ConsentContext, ConsentEventType};
+use crate::link_signer::LinkSigner;
+use crate::routes::error_chain_fmt;
+use crate::utils::escape_html;
+use actix_web::http::header::ContentType;
+use actix_web::{get, http::StatusCode, post, web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+struct Parameters {
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("The unsubscribe link is invalid.")]
+    InvalidToken(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+            UnsubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+// Link scanners and previewers follow GET links in emails, so the GET endpoint only asks for a
+// confirmation. The actual state change happens on POST.
+#[get("/subscriptions/unsubscribe")]
+#[tracing::instrument(name = "Unsubscribe form", skip(parameters, link_signer))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<Parameters>,
+    link_signer: web::Data<LinkSigner>,
+) -> Result<HttpResponse, UnsubscribeError> {
+    link_signer
+        .verify_unsubscribe_token(&parameters.token)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    // A verified token only contains a uuid and a hex tag, both safe to put in a query string
+    let token = escape_html(&parameters.token);
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Unsubscribe</title>
+            </head>
+            <body>
+                <p>Do you want to stop receiving our newsletter?</p>
+                <form action="/subscriptions/unsubscribe?token={token}" method="post">
+                    <button type="submit">Unsubscribe</button>
+                </form>
+            </body>
+            </html>"#,
+        )))
+}
+
+// Also the target of RFC 8058 one-click unsubscribe requests, which POST
+// `List-Unsubscribe=One-Click` to the exact URL found in the `List-Unsubscribe` header.
+#[post("/subscriptions/unsubscribe")]
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(parameters, pool, link_signer, request)
+)]
+pub async fn unsubscribe(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    link_signer: web::Data<LinkSigner>,
+    request: HttpRequest,
+) -> Result<HttpResponse, UnsubscribeError> {
+    let subscriber_id = link_signer
+        .verify_unsubscribe_token(&parameters.token)
+        .map_err(UnsubscribeError::InvalidToken)?;
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool.")?;
+    let unsubscribed_email = mark_subscriber_as_unsubscribed(subscriber_id, &mut transaction)
+        .await
+        .context("Failed to update subscriber status to `unsubscribed`.")?;
+    // Following the link again changes nothing and is not recorded a second time
+    if let Some(email) = unsubscribed_email {
+        record_consent_event(
+            &mut transaction,
+            subscriber_id,
+            ConsentEventType::Unsubscribe,
+            &email,
+            &ConsentContext::from_request(&request),
+            None,
+        )
+        .await
+        .context("Failed to record the withdrawal of consent.")?;
+        drop_pending_deliveries(&email, &mut transaction)
+            .await
+            .context("Failed to drop the pending deliveries of the subscriber.")?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to unsubscribe a subscriber.")?;
+
+    Ok(HttpResponse::Ok().content_type(ContentType::html()).body(
+        r#"<!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta http-equiv="content-type" content="text/html; charset=utf-8">
+                <title>Unsubscribed</title>
+            </head>
+            <body>
+                <p>You have been unsubscribed. You will not receive any further issues.</p>
+            </body>
+            </html>"#,
+    ))
+}
+
+// Returns the email address of the subscriber, unless they were already unsubscribed
+#[tracing::instrument(name = "Mark subscriber as unsubscribed", skip(transaction))]
+async fn mark_subscriber_as_unsubscribed(
+    subscriber_id: Uuid,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<Option<String>, sqlx::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'unsubscribed'
+        WHERE id = $1 AND status <> 'unsubscribed'
+        RETURNING email
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(subscriber.map(|s| s.email))
+}
+
+// Issues being delivered have one recipient less, like when the worker finds the subscriber gone
+#[tracing::instrument(name = "Drop pending deliveries", skip(transaction))]
+async fn drop_pending_deliveries(
+    email: &str,
+    transaction: &mut Transaction<'_, Postgres>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        WITH dropped AS (
+            DELETE FROM issue_delivery_queue
+            WHERE subscriber_email = $1
+            RETURNING newsletter_issue_id
+        )
+        UPDATE newsletter_issues
+        SET
+            n_recipients = n_recipients - 1,
+            delivery_completed_at = CASE
+                WHEN n_delivered + n_failed >= n_recipients - 1 THEN now()
+                ELSE NULL
+            END
+        WHERE newsletter_issue_id IN (SELECT newsletter_issue_id FROM dropped)
+        "#,
+        email
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
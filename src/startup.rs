@@ -1,14 +1,24 @@
 use crate::authentication::{force_password_change_on_weak_password, reject_anonymous_users};
 use crate::configuration::{DatabaseSettings, Settings};
-use crate::email_client::EmailClient;
+use crate::consent::TrustForwardedHeaders;
+use crate::email_client::EmailSender;
+use crate::link_signer::LinkSigner;
 use crate::routes::{
-    admin_dashboard, change_password, change_password_form, confirm, health_check, home, login,
-    login_form, logout, publish_newsletter, publish_newsletter_form, subscribe,
+    admin_dashboard, archived_newsletter_issue, atom_feed, cancel_newsletter_issue, change_email,
+    change_email_form, change_password, change_password_form, confirm, confirm_email_change,
+    create_subscriber_list, delivery_failures, edit_newsletter_issue, edit_newsletter_issue_form,
+    export_subscriber_consent, health_check, home, login, login_form, logout, newsletter_archive,
+    newsletter_draft, newsletter_drafts, newsletter_issue, preferences_form,
+    preview_newsletter_draft, publish_newsletter, publish_newsletter_form,
+    requeue_delivery_failures, reschedule_newsletter_issue, resend_confirmation, rss_feed,
+    save_newsletter_draft, send_newsletter_draft_test, set_newsletter_issue_in_archive, subscribe,
+    subscribe_form, subscriber_consent_history, subscriber_lists, subscriber_search, track_click,
+    track_open, unsubscribe, unsubscribe_form, update_newsletter_draft, update_preferences,
 };
 use actix_session::storage::RedisSessionStore;
 use actix_session::SessionMiddleware;
 use actix_web::cookie::Key;
-use actix_web::dev::Server;
+use actix_web::dev::{Server, ServerHandle};
 use actix_web::{web, App, HttpServer};
 use actix_web_flash_messages::storage::CookieMessageStore;
 use actix_web_flash_messages::FlashMessagesFramework;
@@ -18,6 +28,7 @@ use secrecy::Secret;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
 use std::net::TcpListener;
+use std::sync::Arc;
 use tracing_actix_web::TracingLogger;
 
 pub struct Application {
@@ -49,6 +60,7 @@ impl Application {
             app_base_url,
             configuration.application.hmac_secret,
             configuration.redis_uri,
+            configuration.application.trust_forwarded_headers,
         )
         .await?;
 
@@ -59,6 +71,11 @@ impl Application {
         self.port
     }
 
+    // Used by `main` to stop the server gracefully once the process is asked to shut down
+    pub fn handle(&self) -> ServerHandle {
+        self.server.handle()
+    }
+
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
         self.server.await
     }
@@ -72,15 +89,18 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
 pub async fn run(
     listener: TcpListener,
     db_pool: PgPool,
-    email_client: EmailClient,
+    email_client: Arc<dyn EmailSender>,
     base_url: reqwest::Url,
     hmac_secret: Secret<String>,
     redis_uri: Secret<String>,
+    trust_forwarded_headers: bool,
 ) -> Result<Server, anyhow::Error> {
     // Wrap the connection  in a smart pointer
     let db_pool = web::Data::new(db_pool);
-    let email_client = web::Data::new(email_client);
+    let email_client: web::Data<dyn EmailSender> = web::Data::from(email_client);
+    let link_signer = web::Data::new(LinkSigner::new(base_url.clone(), hmac_secret.clone()));
     let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let trust_forwarded_headers = web::Data::new(TrustForwardedHeaders(trust_forwarded_headers));
 
     // Secret key
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
@@ -100,8 +120,21 @@ pub async fn run(
             ))
             .wrap(messages_framework.clone())
             .service(health_check)
+            .service(subscribe_form)
             .service(subscribe)
+            .service(resend_confirmation)
             .service(confirm)
+            .service(preferences_form)
+            .service(update_preferences)
+            .service(confirm_email_change)
+            .service(unsubscribe_form)
+            .service(unsubscribe)
+            .service(track_click)
+            .service(track_open)
+            .service(newsletter_archive)
+            .service(archived_newsletter_issue)
+            .service(rss_feed)
+            .service(atom_feed)
             .service(home)
             .service(login_form)
             .service(login)
@@ -117,14 +150,41 @@ pub async fn run(
                         web::scope("")
                             .wrap(from_fn(force_password_change_on_weak_password))
                             .service(admin_dashboard)
+                            .service(change_email_form)
+                            .service(change_email)
+                            .service(subscriber_lists)
+                            .service(create_subscriber_list)
+                            .service(subscriber_search)
+                            .service(subscriber_consent_history)
+                            .service(export_subscriber_consent)
                             .service(publish_newsletter_form)
-                            .service(publish_newsletter),
+                            .service(publish_newsletter)
+                            // Drafts have to be registered before `/newsletters/{id}` routes,
+                            // `drafts` would otherwise be matched (and rejected) as an issue id
+                            .service(newsletter_drafts)
+                            .service(save_newsletter_draft)
+                            .service(newsletter_draft)
+                            .service(update_newsletter_draft)
+                            .service(preview_newsletter_draft)
+                            .service(send_newsletter_draft_test)
+                            .service(newsletter_issue)
+                            .service(delivery_failures)
+                            .service(requeue_delivery_failures)
+                            .service(reschedule_newsletter_issue)
+                            .service(cancel_newsletter_issue)
+                            .service(edit_newsletter_issue_form)
+                            .service(edit_newsletter_issue)
+                            .service(set_newsletter_issue_in_archive),
                     ),
             )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(link_signer.clone())
+            .app_data(trust_forwarded_headers.clone())
     })
+    // Signals are handled in `main` so the API and the delivery worker shut down together
+    .disable_signals()
     .listen(listener)?
     .run();
     Ok(server)
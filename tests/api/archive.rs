@@ -0,0 +1,204 @@
+use crate::helper::{
+    assert_is_redirect_to, create_confirmed_subscriber, spawn_app, when_sending_a_newsletter,
+    PostmarkBatchResponder, TestApp,
+};
+use uuid::Uuid;
+
+async fn publish_newsletter(app: &TestApp, title: &str) -> Uuid {
+    let newsletter_body_request = serde_json::json!({
+        "title": title,
+        "html_content": "<p>Hi {{ name }}, here is the news</p>",
+        "text_content": "Hi {{ name }}, here is the news",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_newsletters(&newsletter_body_request).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    sqlx::query!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE title = $1",
+        title
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .newsletter_issue_id
+}
+
+#[tokio::test]
+async fn published_issues_are_listed_in_the_archive() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .mount(&app.email_server)
+        .await;
+    let newsletter_issue_id = publish_newsletter(&app, "First <issue>").await;
+    app.post_logout().await;
+
+    // Act
+    let archive_page = app.get_archive_html(None).await;
+    let response = app.get_archived_issue(newsletter_issue_id).await;
+
+    // Assert
+    assert!(archive_page.contains(&format!(
+        r#"<a href="/archive/{}">First &lt;issue&gt;</a>"#,
+        newsletter_issue_id
+    )));
+    assert_eq!(response.status().as_u16(), 200);
+    let issue_page = response.text().await.unwrap();
+    assert!(issue_page.contains("<h1>First &lt;issue&gt;</h1>"));
+    assert!(issue_page.contains("<p>Hi reader, here is the news</p>"));
+}
+
+#[tokio::test]
+async fn the_archive_is_paginated_newest_first() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    for i in 0..11 {
+        publish_newsletter(&app, &format!("Issue {:02}", i)).await;
+        // Issues published within the same second would be ordered arbitrarily
+        sqlx::query!(
+            "UPDATE newsletter_issues SET published_at = $1 WHERE title = $2",
+            chrono::Utc::now() - chrono::Duration::days(20 - i),
+            format!("Issue {:02}", i)
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    }
+
+    // Act
+    let first_page = app.get_archive_html(None).await;
+    let second_page = app.get_archive_html(Some(2)).await;
+
+    // Assert
+    let newest = first_page.find("Issue 10").unwrap();
+    let older = first_page.find("Issue 09").unwrap();
+    assert!(newest < older);
+    assert!(!first_page.contains("Issue 00"));
+    assert!(first_page.contains(r#"<a href="/archive?page=2">Older issues</a>"#));
+    assert!(!first_page.contains("Newer issues"));
+    assert!(second_page.contains("Issue 00"));
+    assert!(!second_page.contains("Issue 01"));
+    assert!(second_page.contains(r#"<a href="/archive?page=1">Newer issues</a>"#));
+    assert!(!second_page.contains("Older issues"));
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_listed_by_when_they_went_out() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let scheduled_issue_id = publish_newsletter(&app, "Scheduled issue").await;
+    publish_newsletter(&app, "Recent issue").await;
+    // Submitted before the recent issue, but only sent after it
+    sqlx::query!(
+        "UPDATE newsletter_issues SET published_at = $1, scheduled_for = $2 \
+        WHERE newsletter_issue_id = $3",
+        chrono::Utc::now() - chrono::Duration::days(2),
+        chrono::Utc::now() - chrono::Duration::hours(1),
+        scheduled_issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "UPDATE newsletter_issues SET published_at = $1 WHERE title = 'Recent issue'",
+        chrono::Utc::now() - chrono::Duration::days(1),
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let archive_page = app.get_archive_html(None).await;
+
+    // Assert
+    let scheduled = archive_page.find("Scheduled issue").unwrap();
+    let recent = archive_page.find("Recent issue").unwrap();
+    assert!(scheduled < recent);
+}
+
+#[tokio::test]
+async fn issues_hidden_from_the_archive_are_not_public() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = publish_newsletter(&app, "Hidden issue").await;
+
+    // Act 1 - Hide the issue
+    let response = app
+        .post_set_newsletter_issue_in_archive(newsletter_issue_id, false)
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/{}", newsletter_issue_id),
+    );
+    let html_page = app.get_newsletter_issue_html(newsletter_issue_id).await;
+    assert!(html_page.contains("The issue is now hidden from the public archive."));
+
+    // Assert
+    assert!(!app.get_archive_html(None).await.contains("Hidden issue"));
+    let response = app.get_archived_issue(newsletter_issue_id).await;
+    assert_eq!(response.status().as_u16(), 404);
+
+    // Act 2 - Show it again
+    app.post_set_newsletter_issue_in_archive(newsletter_issue_id, true)
+        .await;
+
+    // Assert
+    assert!(app.get_archive_html(None).await.contains("Hidden issue"));
+    let response = app.get_archived_issue(newsletter_issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_not_in_the_archive_before_they_are_due() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let scheduled_for = (chrono::Utc::now() + chrono::Duration::hours(1))
+        .format("%Y-%m-%dT%H:%M")
+        .to_string();
+    let newsletter_body_request = serde_json::json!({
+        "title": "Upcoming issue",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "text_content": "Newsletter body as plain text",
+        "scheduled_for": scheduled_for,
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_body_request).await;
+    let newsletter_issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+
+    // Act
+    let archive_page = app.get_archive_html(None).await;
+    let response = app.get_archived_issue(newsletter_issue_id).await;
+
+    // Assert
+    assert!(!archive_page.contains("Upcoming issue"));
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_hide_an_issue_from_the_archive() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = publish_newsletter(&app, "Public issue").await;
+    app.post_logout().await;
+
+    // Act
+    let response = app
+        .post_set_newsletter_issue_in_archive(newsletter_issue_id, false)
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_archive_html(None).await.contains("Public issue"));
+}
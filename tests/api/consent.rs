@@ -0,0 +1,165 @@
+use crate::helper::{
+    assert_is_redirect_to, create_confirmed_subscriber_with, spawn_app, TestApp, TestSubscriber,
+};
+use uuid::Uuid;
+
+// Signs up and confirms from two different clients, as if the link was opened on a phone.
+// The forwarded addresses are made up by the clients, no trusted proxy sets them in the tests.
+const SUBSCRIBER: TestSubscriber = TestSubscriber {
+    name: Some("John73"),
+    email: Some("john_r77@gmail.com"),
+    list_ids: &[],
+    source: Some("subscribe_form"),
+    signup_headers: &[
+        ("X-Forwarded-For", "198.51.100.4"),
+        ("User-Agent", "Signup browser"),
+    ],
+    confirmation_headers: &[
+        ("X-Forwarded-For", "203.0.113.9"),
+        ("User-Agent", "Mail client"),
+    ],
+};
+
+async fn get_consent_export(app: &TestApp, subscriber_id: Uuid) -> reqwest::Response {
+    app.api_client
+        .get(format!(
+            "{}/admin/subscribers/{}/consent.json",
+            app.address, subscriber_id
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn signups_and_confirmations_are_recorded_as_consent() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = create_confirmed_subscriber_with(&app, SUBSCRIBER).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = get_consent_export(&app, subscriber_id).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .headers()
+        .get("Content-Disposition")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .contains(&format!("consent-{}.json", subscriber_id)));
+    let export: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(export["subscriber"]["email"], "john_r77@gmail.com");
+    assert_eq!(export["subscriber"]["status"], "confirmed");
+    let events = export["consent_events"].as_array().unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["event_type"], "signup");
+    assert_eq!(events[0]["ip_address"], "127.0.0.1");
+    assert_eq!(events[0]["user_agent"], "Signup browser");
+    assert_eq!(events[0]["source"], "subscribe_form");
+    assert_eq!(events[1]["event_type"], "confirmation");
+    assert_eq!(events[1]["ip_address"], "127.0.0.1");
+    assert_eq!(events[1]["user_agent"], "Mail client");
+    assert_eq!(events[1]["email"], "john_r77@gmail.com");
+}
+
+#[tokio::test]
+async fn unsubscribing_is_recorded_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = create_confirmed_subscriber_with(&app, SUBSCRIBER).await;
+    let mut unsubscribe_url = app.link_signer.unsubscribe_url(subscriber_id);
+    unsubscribe_url.set_port(Some(app.port)).unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    for _ in 0..2 {
+        app.api_client
+            .post(unsubscribe_url.clone())
+            .header("X-Forwarded-For", "192.0.2.1")
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    // Assert
+    let export: serde_json::Value = get_consent_export(&app, subscriber_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    let events = export["consent_events"].as_array().unwrap();
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[2]["event_type"], "unsubscribe");
+    assert_eq!(events[2]["ip_address"], "127.0.0.1");
+}
+
+#[tokio::test]
+async fn the_consent_history_is_listed_in_the_admin_area() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = create_confirmed_subscriber_with(&app, SUBSCRIBER).await;
+    app.test_user.login(&app).await;
+
+    // Act 1 - Find the subscriber
+    let html_page = app
+        .api_client
+        .get(format!("{}/admin/subscribers?email=JOHN_R77", app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    let history_link = format!("/admin/subscribers/{}/consent", subscriber_id);
+    assert!(html_page.contains(&history_link));
+
+    // Act 2 - Open their history
+    let html_page = app
+        .api_client
+        .get(format!("{}{}", app.address, history_link))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains("Consent history of john_r77@gmail.com"));
+    assert!(html_page.contains("<td>signup</td>"));
+    assert!(html_page.contains("<td>confirmation</td>"));
+    assert!(html_page.contains("<td>127.0.0.1</td>"));
+    assert!(!html_page.contains("198.51.100.4"));
+}
+
+#[tokio::test]
+async fn the_consent_history_of_an_unknown_subscriber_is_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = get_consent_export(&app, Uuid::new_v4()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_consent_records() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = get_consent_export(&app, Uuid::new_v4()).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
@@ -0,0 +1,199 @@
+use crate::helper::{assert_is_redirect_to, spawn_app, TestApp};
+use uuid::Uuid;
+
+async fn publish_newsletter(app: &TestApp, title: &str) -> Uuid {
+    let newsletter_body_request = serde_json::json!({
+        "title": title,
+        "html_content": "<p>Hi {{ name }}, here is the <b>news</b></p>",
+        "text_content": "Hi {{ name }}, here is the news",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_newsletters(&newsletter_body_request).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    sqlx::query!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE title = $1",
+        title
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .newsletter_issue_id
+}
+
+async fn get_feed(app: &TestApp, feed: &str, headers: &[(&str, &str)]) -> reqwest::Response {
+    let mut request = app.api_client.get(format!("{}/{}", app.address, feed));
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    request.send().await.expect("Failed to execute request.")
+}
+
+#[tokio::test]
+async fn the_rss_feed_lists_published_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = publish_newsletter(&app, "News & views").await;
+
+    // Act
+    let response = get_feed(&app, "feed.rss", &[]).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/rss+xml; charset=utf-8"
+    );
+    assert!(response.headers().contains_key("ETag"));
+    assert!(response.headers().contains_key("Last-Modified"));
+    let feed = response.text().await.unwrap();
+    assert!(feed.contains("<title>News &amp; views</title>"));
+    assert!(feed.contains(&format!(
+        r#"<guid isPermaLink="false">urn:uuid:{}</guid>"#,
+        newsletter_issue_id
+    )));
+    assert!(feed.contains(&format!("/archive/{}</link>", newsletter_issue_id)));
+    assert!(feed.contains("<pubDate>"));
+    assert!(feed.contains(
+        "<description><![CDATA[<p>Hi reader, here is the <b>news</b></p>]]></description>"
+    ));
+}
+
+#[tokio::test]
+async fn the_atom_feed_lists_published_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = publish_newsletter(&app, "News & views").await;
+
+    // Act
+    let response = get_feed(&app, "feed.atom", &[]).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/atom+xml; charset=utf-8"
+    );
+    let feed = response.text().await.unwrap();
+    assert!(feed.contains("<title>News &amp; views</title>"));
+    assert!(feed.contains(&format!("<id>urn:uuid:{}</id>", newsletter_issue_id)));
+    assert!(feed.contains("<updated>"));
+    assert!(feed.contains(r#"<content type="html">&lt;p&gt;Hi reader"#));
+}
+
+#[tokio::test]
+async fn hidden_issues_are_left_out_of_the_feeds() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = publish_newsletter(&app, "Hidden issue").await;
+    app.post_set_newsletter_issue_in_archive(newsletter_issue_id, false)
+        .await;
+
+    for feed in ["feed.rss", "feed.atom"] {
+        // Act
+        let response = get_feed(&app, feed, &[]).await;
+
+        // Assert
+        assert!(!response.text().await.unwrap().contains("Hidden issue"));
+    }
+}
+
+#[tokio::test]
+async fn unchanged_feeds_are_not_sent_again() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app, "First issue").await;
+    let response = get_feed(&app, "feed.rss", &[]).await;
+    let etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+    let last_modified = response.headers()["Last-Modified"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+
+    // Act 1 - Nothing was published since
+    let by_etag = get_feed(&app, "feed.rss", &[("If-None-Match", &etag)]).await;
+    let by_date = get_feed(&app, "feed.atom", &[("If-Modified-Since", &last_modified)]).await;
+
+    // Assert
+    assert_eq!(by_etag.status().as_u16(), 304);
+    assert_eq!(by_etag.headers()["ETag"], etag.as_str());
+    assert!(by_etag.text().await.unwrap().is_empty());
+    assert_eq!(by_date.status().as_u16(), 304);
+
+    // Act 2 - A new issue is published
+    publish_newsletter(&app, "Second issue").await;
+    sqlx::query!(
+        "UPDATE newsletter_issues SET published_at = '2999-01-01T00:00:00Z' WHERE title = 'Second issue'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let by_etag = get_feed(&app, "feed.rss", &[("If-None-Match", &etag)]).await;
+    let by_date = get_feed(&app, "feed.atom", &[("If-Modified-Since", &last_modified)]).await;
+
+    // Assert
+    assert_eq!(by_etag.status().as_u16(), 200);
+    assert_ne!(by_etag.headers()["ETag"], etag.as_str());
+    assert!(by_etag.text().await.unwrap().contains("Second issue"));
+    assert_eq!(by_date.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn hiding_an_issue_changes_the_feeds() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app, "First issue").await;
+    let newsletter_issue_id = publish_newsletter(&app, "Second issue").await;
+    // Published well before the issue is hidden, in another second than Last-Modified
+    sqlx::query!("UPDATE newsletter_issues SET published_at = '2000-01-01T00:00:00Z'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let response = get_feed(&app, "feed.rss", &[]).await;
+    let last_modified = response.headers()["Last-Modified"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+
+    // Act
+    app.post_set_newsletter_issue_in_archive(newsletter_issue_id, false)
+        .await;
+    let by_date = get_feed(&app, "feed.atom", &[("If-Modified-Since", &last_modified)]).await;
+
+    // Assert
+    assert_eq!(by_date.status().as_u16(), 200);
+    assert!(!by_date.text().await.unwrap().contains("Second issue"));
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_dated_when_they_became_visible() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    publish_newsletter(&app, "Scheduled issue").await;
+    // An issue submitted long before its scheduled time, which has now passed
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET published_at = '2000-01-01T00:00:00Z', scheduled_for = '2001-01-01T00:00:00Z'
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = get_feed(&app, "feed.atom", &[]).await;
+
+    // Assert
+    assert_eq!(
+        response.headers()["Last-Modified"],
+        "Mon, 01 Jan 2001 00:00:00 GMT"
+    );
+    let feed = response.text().await.unwrap();
+    assert!(feed.contains("<published>2001-01-01T00:00:00+00:00</published>"));
+}
@@ -1,12 +1,25 @@
 use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+use fake::faker::internet::en::SafeEmail;
+use fake::Fake;
 use once_cell::sync::Lazy;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
-use zero2prod::email_client::EmailClient;
-use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockBuilder, MockServer, Request, Respond, ResponseTemplate};
+use zero2prod::configuration::{
+    get_configuration, DatabaseSettings, EmailBackend, Settings, WorkerSettings,
+};
+use zero2prod::email_client::EmailSender;
+use zero2prod::email_outbox_worker::try_execute_outbox_task;
+use zero2prod::issue_delivery_worker::{
+    run_worker_until_stopped, try_execute_task, ExecutionOutcome,
+};
+use zero2prod::link_signer::LinkSigner;
 use zero2prod::startup::{get_connection_pool, Application};
 use zero2prod::telemetry::{get_subscriber, subscriber_init};
 
@@ -35,7 +48,10 @@ pub struct TestApp {
     pub api_client: reqwest::Client,
     pub port: u16,
     pub test_user: TestUser,
-    pub email_client: EmailClient,
+    pub email_client: Arc<dyn EmailSender>,
+    pub worker_settings: WorkerSettings,
+    pub link_signer: LinkSigner,
+    pub configuration: Settings,
 }
 
 pub struct TestUser {
@@ -88,15 +104,43 @@ impl TestUser {
 impl TestApp {
     pub async fn dispatch_all_pending_emails(&self) {
         loop {
-            if let ExecutionOutcome::EmptyQueue =
-                try_execute_task(&self.db_pool, &self.email_client)
-                    .await
-                    .unwrap()
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.worker_settings,
+                &self.link_signer,
+            )
+            .await
+            .unwrap()
             {
                 break;
             }
         }
     }
+    pub async fn dispatch_all_outbox_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_outbox_task(
+                &self.db_pool,
+                self.email_client.as_ref(),
+                &self.worker_settings,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+    // Runs the delivery worker pool in the background until the returned token is cancelled
+    pub fn spawn_worker(&self) -> (CancellationToken, JoinHandle<Result<(), anyhow::Error>>) {
+        let shutdown = CancellationToken::new();
+        let worker = tokio::spawn(run_worker_until_stopped(
+            self.configuration.clone(),
+            shutdown.clone(),
+        ));
+        (shutdown, worker)
+    }
+
     pub async fn post_logout(&self) -> reqwest::Response {
         self.api_client
             .post(format!("{}/admin/logout", self.address))
@@ -109,7 +153,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/password", self.address))
+            .post(format!("{}/admin/password", self.address))
             .form(body)
             .send()
             .await
@@ -127,7 +171,7 @@ impl TestApp {
     }
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/dashboard", self.address))
+            .get(format!("{}/admin/dashboard", self.address))
             .send()
             .await
             .expect("Failed to execute request")
@@ -140,7 +184,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", self.address))
+            .post(format!("{}/login", self.address))
             .form(body)
             .send()
             .await
@@ -157,20 +201,40 @@ impl TestApp {
             .unwrap()
     }
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
-        self.api_client
-            .post(&format!("{}/subscriptions", self.address))
+        let response = self
+            .api_client
+            .post(format!("{}/subscriptions", self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
             .await
-            .expect("Failed to excute request")
+            .expect("Failed to excute request");
+        // Confirmation emails go through the outbox, send them as the worker would
+        self.dispatch_all_outbox_emails().await;
+        response
+    }
+    pub async fn post_resend_confirmation(&self, body: String) -> reqwest::Response {
+        let response = self
+            .api_client
+            .post(format!(
+                "{}/subscriptions/resend-confirmation",
+                self.address
+            ))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to excute request");
+        // Confirmation emails go through the outbox, send them as the worker would
+        self.dispatch_all_outbox_emails().await;
+        response
     }
     pub async fn post_newsletters<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/newsletters", self.address))
+            .post(format!("{}/admin/newsletters", self.address))
             .form(body)
             .send()
             .await
@@ -186,6 +250,253 @@ impl TestApp {
     pub async fn get_newsletters_html(&self) -> String {
         self.get_newsletters().await.text().await.unwrap()
     }
+    pub async fn get_newsletter_issue_html(&self, newsletter_issue_id: Uuid) -> String {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/{}",
+                self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+    pub async fn get_delivery_failures(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/{}/failures",
+                self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    pub async fn get_delivery_failures_html(&self, newsletter_issue_id: Uuid) -> String {
+        self.get_delivery_failures(newsletter_issue_id)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+    pub async fn post_requeue_delivery_failures(
+        &self,
+        newsletter_issue_id: Uuid,
+        body: String,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/failures",
+                self.address, newsletter_issue_id
+            ))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn post_reschedule_newsletter_issue<Body>(
+        &self,
+        newsletter_issue_id: Uuid,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/schedule",
+                self.address, newsletter_issue_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn get_edit_newsletter_issue_html(&self, newsletter_issue_id: Uuid) -> String {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/{}/edit",
+                self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+    pub async fn post_edit_newsletter_issue<Body>(
+        &self,
+        newsletter_issue_id: Uuid,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/edit",
+                self.address, newsletter_issue_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn post_cancel_newsletter_issue(
+        &self,
+        newsletter_issue_id: Uuid,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/cancel",
+                self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn post_set_newsletter_issue_in_archive(
+        &self,
+        newsletter_issue_id: Uuid,
+        in_archive: bool,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/{}/archive",
+                self.address, newsletter_issue_id
+            ))
+            .form(&serde_json::json!({ "in_archive": in_archive }))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn get_archive(&self, page: Option<u32>) -> reqwest::Response {
+        let url = match page {
+            Some(page) => format!("{}/archive?page={}", self.address, page),
+            None => format!("{}/archive", self.address),
+        };
+        self.api_client
+            .get(url)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn get_archive_html(&self, page: Option<u32>) -> String {
+        self.get_archive(page).await.text().await.unwrap()
+    }
+    pub async fn get_archived_issue(&self, newsletter_issue_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/archive/{}", self.address, newsletter_issue_id))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn get_lists_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/lists", self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+    pub async fn post_lists<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/lists", self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn get_drafts_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/newsletters/drafts", self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+    pub async fn get_draft(&self, draft_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/drafts/{}",
+                self.address, draft_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+    pub async fn get_draft_html(&self, draft_id: Uuid) -> String {
+        self.get_draft(draft_id).await.text().await.unwrap()
+    }
+    pub async fn get_draft_preview_html(&self, draft_id: Uuid) -> String {
+        self.api_client
+            .get(format!(
+                "{}/admin/newsletters/drafts/{}/preview",
+                self.address, draft_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request")
+            .text()
+            .await
+            .unwrap()
+    }
+    pub async fn post_save_draft<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/newsletters/drafts", self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn post_update_draft<Body>(&self, draft_id: Uuid, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/drafts/{}",
+                self.address, draft_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn post_send_draft_test(&self, draft_id: Uuid) -> reqwest::Response {
+        self.api_client
+            .post(format!(
+                "{}/admin/newsletters/drafts/{}/test",
+                self.address, draft_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+    pub async fn post_change_email<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/email", self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
@@ -207,8 +518,8 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
         ConfirmationLinks { html, plain_text }
     }
 }
@@ -222,7 +533,11 @@ pub async fn spawn_app() -> TestApp {
         //Retrieve random port assigned by the OS
         configuration.application.port = 0;
         // Mock server as an wmail API
+        configuration.email_client.backend = EmailBackend::Postmark;
         configuration.email_client.base_url = email_server.uri();
+        // Retry failed deliveries right away instead of waiting for the backoff
+        configuration.worker.initial_backoff_milliseconds = 0;
+        configuration.worker.poll_interval_milliseconds = 50;
         configuration
     };
 
@@ -235,7 +550,7 @@ pub async fn spawn_app() -> TestApp {
 
     let address = format!("http://127.0.0.1:{}", application_port);
 
-    let _ = tokio::spawn(application.run_until_stopped());
+    drop(tokio::spawn(application.run_until_stopped()));
 
     let api_client = reqwest::Client::builder()
         .redirect(reqwest::redirect::Policy::none())
@@ -250,7 +565,10 @@ pub async fn spawn_app() -> TestApp {
         api_client,
         port: application_port,
         test_user: TestUser::generate(),
-        email_client: configuration.email_client.client(),
+        email_client: configuration.email_client.clone().client(),
+        worker_settings: configuration.worker.clone(),
+        link_signer: configuration.application.link_signer(),
+        configuration,
     };
 
     test_app.test_user.store(&test_app.db_pool).await;
@@ -282,3 +600,157 @@ pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location);
 }
+
+// What a test subscriber signs up with, a random email is made up when left out
+#[derive(Default, Clone, Copy)]
+pub struct TestSubscriber<'a> {
+    pub name: Option<&'a str>,
+    pub email: Option<&'a str>,
+    pub list_ids: &'a [Uuid],
+    pub source: Option<&'a str>,
+    // Sent along with the signup and the confirmation request respectively
+    pub signup_headers: &'a [(&'a str, &'a str)],
+    pub confirmation_headers: &'a [(&'a str, &'a str)],
+}
+
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    create_unconfirmed_subscriber_with(app, TestSubscriber::default()).await
+}
+
+pub async fn create_unconfirmed_subscriber_with(
+    app: &TestApp,
+    subscriber: TestSubscriber<'_>,
+) -> ConfirmationLinks {
+    // Fake names can contain characters subscriber names may not, e.g. `'`
+    let name = subscriber.name.unwrap_or("Ursula").to_owned();
+    let email: String = subscriber
+        .email
+        .map_or_else(|| SafeEmail().fake(), ToOwned::to_owned);
+    // Checkboxes send one `list_id` pair per selected list
+    let mut fields = vec![("name", name), ("email", email)];
+    if let Some(source) = subscriber.source {
+        fields.push(("source", source.to_owned()));
+    }
+    for list_id in subscriber.list_ids {
+        fields.push(("list_id", list_id.to_string()));
+    }
+    let body = serde_urlencoded::to_string(fields).unwrap();
+
+    // We need to use _veriable_name for the guard to be droped at the end of the scope
+    // We can not use _
+    let _mock_guard = when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let mut request = app
+        .api_client
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body);
+    for (name, value) in subscriber.signup_headers {
+        request = request.header(*name, *value);
+    }
+    request
+        .send()
+        .await
+        .expect("Failed to excute request")
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_outbox_emails().await;
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    create_confirmed_subscriber_with(app, TestSubscriber::default()).await;
+}
+
+// Returns the id of the new subscriber
+pub async fn create_confirmed_subscriber_with(
+    app: &TestApp,
+    subscriber: TestSubscriber<'_>,
+) -> Uuid {
+    let email: String = subscriber
+        .email
+        .map_or_else(|| SafeEmail().fake(), ToOwned::to_owned);
+    let subscriber = TestSubscriber {
+        email: Some(&email),
+        ..subscriber
+    };
+    let confirmation_link = create_unconfirmed_subscriber_with(app, subscriber).await;
+    let mut request = app.api_client.get(confirmation_link.html);
+    for (name, value) in subscriber.confirmation_headers {
+        request = request.header(*name, *value);
+    }
+    request.send().await.unwrap().error_for_status().unwrap();
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+// short hand for a commaon mocking setup
+pub fn when_sending_an_email() -> MockBuilder {
+    Mock::given(path("/email")).and(method("POST"))
+}
+
+// Newsletter issues go out through the batch endpoint
+pub fn when_sending_a_newsletter() -> MockBuilder {
+    Mock::given(path("/email/batch")).and(method("POST"))
+}
+
+// Answers a batch request the way Postmark does: one entry per message, in order.
+#[derive(Default)]
+pub struct PostmarkBatchResponder {
+    rejected_recipient: Option<String>,
+    delay: Option<Duration>,
+}
+
+impl PostmarkBatchResponder {
+    pub fn rejecting(recipient: &str) -> Self {
+        Self {
+            rejected_recipient: Some(recipient.to_string()),
+            delay: None,
+        }
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+}
+
+impl Respond for PostmarkBatchResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let body: Vec<_> = messages
+            .iter()
+            .map(|message| {
+                if message["To"].as_str() == self.rejected_recipient.as_deref() {
+                    serde_json::json!({
+                        "ErrorCode": 406,
+                        "Message": "You tried to send to a recipient that has been marked as inactive."
+                    })
+                } else {
+                    serde_json::json!({"ErrorCode": 0, "Message": "OK"})
+                }
+            })
+            .collect();
+        let response = ResponseTemplate::new(200).set_body_json(body);
+        match self.delay {
+            Some(delay) => response.set_delay(delay),
+            None => response,
+        }
+    }
+}
@@ -1,8 +1,17 @@
 mod admin_dashboard;
+mod archive;
 mod change_password;
+mod consent;
+mod feeds;
 mod health_check;
 mod helper;
 mod login;
 mod newsletter;
+mod newsletter_drafts;
+mod scheduled_newsletter;
+mod subscriber_lists;
+mod subscription_preferences;
 mod subscriptions;
 mod subscriptions_confirmation;
+mod tracking;
+mod unsubscribe;
@@ -1,10 +1,9 @@
-use crate::helper::{assert_is_redirect_to, spawn_app, ConfirmationLinks, TestApp};
-use fake::faker::internet::en::SafeEmail;
-use fake::faker::name::en::Name;
-use fake::Fake;
+use crate::helper::{
+    assert_is_redirect_to, create_confirmed_subscriber, create_unconfirmed_subscriber, spawn_app,
+    when_sending_a_newsletter, PostmarkBatchResponder,
+};
 use std::time::Duration;
-use wiremock::matchers::{any, method, path};
-use wiremock::MockBuilder;
+use wiremock::matchers::any;
 use wiremock::{Mock, ResponseTemplate};
 
 #[tokio::test]
@@ -83,20 +82,11 @@ async fn newsletters_form_send_appropriate_message_on_empty_fields() {
         (
             serde_json::json!({
                 "title": "Newsletter Title",
-                "html_content":"<p>Newsletter body as HTML</P>",
-                "text_content":"",
-                "idempotency_key": uuid::Uuid::new_v4().to_string()
-            }),
-            r#"<p><i>Plain text content cannot be empty.</i></p>"#,
-        ),
-        (
-            serde_json::json!({
-                "title": "Newsletter Title",
-                "html_content":"<p>Newsletter body as HTML</P>",
+                "html_content":"<p><img src=\"https://example.com/banner.png\"></p>",
                 "text_content":"      ",
                 "idempotency_key": uuid::Uuid::new_v4().to_string()
             }),
-            r#"<p><i>Plain text content cannot be empty.</i></p>"#,
+            r#"<p><i>Plain text content cannot be empty, the HTML content has no text to derive it from.</i></p>"#,
         ),
     ];
 
@@ -148,8 +138,8 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     app.test_user.login(&app).await;
     create_confirmed_subscriber(&app).await;
 
-    when_sending_an_email()
-        .respond_with(ResponseTemplate::new(200))
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -172,14 +162,313 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     app.dispatch_all_pending_emails().await;
 }
 #[tokio::test]
-async fn newsletter_creation_is_idempotent() {
+async fn the_plain_text_body_is_derived_from_the_html_when_omitted() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content":"<h1>News</h1><p>Read <a href=\"https://example.com\">this</a>.</p>",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+
+    // Act
+    let response = app.post_newsletters(&newsletter_body_request).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let expected = "# News\n\nRead [this][1].\n\n[1]: https://example.com";
+    let saved = sqlx::query!("SELECT text_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.text_content, expected);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body[0]["TextBody"].as_str().unwrap().starts_with(expected));
+}
+
+#[tokio::test]
+async fn newsletters_can_be_written_in_markdown() {
     // Arrange
     let app = spawn_app().await;
     app.test_user.login(&app).await;
     create_confirmed_subscriber(&app).await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let markdown = "# News\n\nHi {{ name }}, read *this*.\n\n<script>alert(1)</script>";
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "format": "markdown",
+        "markdown_content": markdown,
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
 
-    when_sending_an_email()
+    // Act
+    let response = app.post_newsletters(&newsletter_body_request).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let saved =
+        sqlx::query!("SELECT html_content, text_content, markdown_content FROM newsletter_issues")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(saved.markdown_content.as_deref(), Some(markdown));
+    assert!(saved
+        .html_content
+        .starts_with("<h1>News</h1>\n<p>Hi {{ name }}, read <em>this</em>.</p>"));
+    assert!(saved.html_content.contains("&lt;script&gt;"));
+    assert!(saved
+        .text_content
+        .starts_with("# News\n\nHi {{ name }}, read *this*."));
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body[0]["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("<h1>News</h1>"));
+}
+
+#[tokio::test]
+async fn markdown_newsletters_require_markdown_content() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter Title",
+            "format": "markdown",
+            "html_content": "<p>Ignored in Markdown mode</p>",
+            "markdown_content": "  ",
+            "idempotency_key": uuid::Uuid::new_v4().to_string()
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains("<p><i>Markdown content cannot be empty.</i></p>"));
+}
+
+#[tokio::test]
+async fn unsafe_html_and_relative_links_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
         .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content": r#"<p>Hi</p><script>alert(1)</script>
+            <a href="javascript:alert(1)">click</a> <a href="/archive">archive</a>"#,
+        "text_content": "Hi",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+
+    // Act
+    let response = app.post_newsletters(&newsletter_body_request).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains("<p><i>Newsletters cannot contain &lt;script&gt; elements.</i></p>"));
+    assert!(html_page.contains("<p><i>javascript:alert(1) is not allowed as a link.</i></p>"));
+    assert!(html_page.contains("The relative link &quot;/archive&quot; will not resolve"));
+    let n_issues = sqlx::query!("SELECT count(*) as \"n!\" FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn published_html_is_restricted_to_an_email_safe_allowlist() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content": r#"<style>p {}</style><p class="intro">Hi <b>there</p><form><input></form>"#,
+        "text_content": "Hi there",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+
+    // Act
+    let response = app.post_newsletters(&newsletter_body_request).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Assert
+    let saved = sqlx::query!("SELECT html_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.html_content, "<p>Hi <b>there</b></p>");
+}
+
+#[tokio::test]
+async fn newsletters_are_personalised_for_each_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber = sqlx::query!("SELECT name, email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content":"<p>Hi {{ email }}</p>",
+        "text_content":"Hi {{ name }}, leave at {{ unsubscribe_url }}",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+
+    // Act
+    let response = app.post_newsletters(&newsletter_body_request).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html_body = body[0]["HtmlBody"].as_str().unwrap();
+    let text_body = body[0]["TextBody"].as_str().unwrap();
+    assert!(html_body.starts_with(&format!("<p>Hi {}</p>", subscriber.email)));
+    let greeting = format!("Hi {}, leave at http", subscriber.name);
+    assert!(text_body.starts_with(&greeting));
+    assert!(text_body.contains("/subscriptions/unsubscribe?token="));
+}
+
+#[tokio::test]
+async fn newsletters_with_an_invalid_placeholder_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let test_cases = vec![
+        (
+            "<p>Hi {{ first_name }}</p>",
+            "Hi",
+            "Html content has an invalid placeholder.",
+        ),
+        (
+            "<p>Hi</p>",
+            "Hi {{ name",
+            "Plain text content has an invalid placeholder.",
+        ),
+    ];
+
+    for (html_content, text_content, error_message) in test_cases {
+        // Act
+        let response = app
+            .post_newsletters(&serde_json::json!({
+                "title": "Newsletter Title",
+                "html_content": html_content,
+                "text_content": text_content,
+                "idempotency_key": uuid::Uuid::new_v4().to_string()
+            }))
+            .await;
+
+        // Assert
+        assert_is_redirect_to(&response, "/admin/newsletters");
+        let html_page = app.get_newsletters_html().await;
+        assert!(html_page.contains(error_message));
+    }
+    app.dispatch_all_pending_emails().await;
+    let n_issues = sqlx::query!("SELECT count(*) as \"n!\" FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn published_issues_are_listed_newest_first() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    for title in ["Older issue", "Newer issue"] {
+        let newsletter_request_body = serde_json::json!({
+            "title": title,
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "text_content": "Newsletter body as plain text",
+            "idempotency_key": uuid::Uuid::new_v4().to_string()
+        });
+        app.post_newsletters(&newsletter_request_body).await;
+    }
+    // Both issues were published within the same second, tell them apart by a full day
+    sqlx::query!(
+        "UPDATE newsletter_issues SET published_at = published_at - interval '1 day' WHERE title = 'Older issue'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let html_page = app.get_newsletters_html().await;
+
+    // Assert
+    let newer = html_page.find("Newer issue").unwrap();
+    let older = html_page.find("Older issue").unwrap();
+    assert!(newer < older);
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -218,10 +507,10 @@ async fn concurrent_form_submission_is_handled_gracefulliy() {
     app.test_user.login(&app).await;
     create_confirmed_subscriber(&app).await;
 
-    when_sending_an_email()
+    when_sending_a_newsletter()
         // We need a long enough delay to ensure that the
         // second request arrives before the first one completes
-        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+        .respond_with(PostmarkBatchResponder::default().with_delay(Duration::from_secs(2)))
         .expect(1)
         .mount(&app.email_server)
         .await;
@@ -249,49 +538,450 @@ async fn concurrent_form_submission_is_handled_gracefulliy() {
 
     //Mock verifes on Drop that we have sent the newsletter email --ONCE--
 }
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let name: String = Name().fake();
-    let email: String = SafeEmail().fake();
-    let body = serde_urlencoded::to_string(&serde_json::json!({
-        "name": name,
-        "email": email
-    }))
-    .unwrap();
+#[tokio::test]
+async fn transient_delivery_failures_are_retried() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
 
-    // We need to use _veriable_name for the guard to be droped at the end of the scope
-    // We can not use _
-    let _mock_guard = when_sending_an_email()
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
+    when_sending_a_newsletter()
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content":"<p>Newsletter body as HTML</P>",
+        "text_content":"Newsletter body as plain text",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+
+    // Act
+    let response = app.post_newsletters(&newsletter_body_request).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_queued = sqlx::query!("SELECT COUNT(*) as \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+    //Mock verifes on Drop that the first attempt failed and the retry succeeded
+}
+
+#[tokio::test]
+async fn delivery_is_abandoned_after_the_maximum_number_of_attempts() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    when_sending_a_newsletter()
+        .respond_with(ResponseTemplate::new(500))
+        .expect(app.worker_settings.max_attempts as u64)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content":"<p>Newsletter body as HTML</P>",
+        "text_content":"Newsletter body as plain text",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+
+    // Act
+    let response = app.post_newsletters(&newsletter_body_request).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_queued = sqlx::query!("SELECT COUNT(*) as \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+    let failure = sqlx::query!("SELECT n_attempts, error FROM issue_delivery_failures")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the failed delivery.");
+    assert_eq!(failure.n_attempts, app.worker_settings.max_attempts);
+    assert!(failure.error.contains("500"));
+}
+
+#[tokio::test]
+async fn user_must_be_logged_in_to_see_failed_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_delivery_failures(uuid::Uuid::new_v4()).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login")
+}
+
+#[tokio::test]
+async fn failed_deliveries_can_be_listed_and_requeued() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+
+    let failing_mock_guard = when_sending_a_newsletter()
+        .respond_with(ResponseTemplate::new(500))
+        .expect(app.worker_settings.max_attempts as u64)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content":"<p>Newsletter body as HTML</P>",
+        "text_content":"Newsletter body as plain text",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_body_request).await;
+    app.dispatch_all_pending_emails().await;
+    drop(failing_mock_guard);
+    let newsletter_issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+
+    // Act 1 -- list the failed deliveries
+    let html_page = app.get_delivery_failures_html(newsletter_issue_id).await;
+    assert!(html_page.contains(&subscriber_email));
+
+    // Act 2 -- requeue them once the provider is back
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
         .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let body = serde_urlencoded::to_string([("subscriber_email", &subscriber_email)]).unwrap();
+    let response = app
+        .post_requeue_delivery_failures(newsletter_issue_id, body)
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/{}/failures", newsletter_issue_id),
+    );
+
+    // Act 3 -- follow redirect
+    let html_page = app.get_delivery_failures_html(newsletter_issue_id).await;
+    assert!(html_page.contains("<p><i>1 deliveries have been requeued.</i></p>"));
+    assert!(!html_page.contains(&format!(r#"value="{}""#, subscriber_email)));
+
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn requeueing_a_failure_that_is_already_queued_keeps_the_progress_consistent() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let subscriber_email = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+
+    let failing_mock_guard = when_sending_a_newsletter()
+        .respond_with(ResponseTemplate::new(500))
+        .expect(app.worker_settings.max_attempts as u64)
         .mount_as_scoped(&app.email_server)
         .await;
 
-    app.post_subscriptions(body.into())
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content":"<p>Newsletter body as HTML</P>",
+        "text_content":"Newsletter body as plain text",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_body_request).await;
+    app.dispatch_all_pending_emails().await;
+    drop(failing_mock_guard);
+    let newsletter_issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
         .await
-        .error_for_status()
-        .unwrap();
+        .unwrap()
+        .newsletter_issue_id;
+    sqlx::query!(
+        "INSERT INTO issue_delivery_queue (newsletter_issue_id, subscriber_email) VALUES ($1, $2)",
+        newsletter_issue_id,
+        subscriber_email
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
 
-    let email_request = &app
+    // Act 1 -- requeue the failure of the address that is queued already
+    let body = serde_urlencoded::to_string([("subscriber_email", &subscriber_email)]).unwrap();
+    app.post_requeue_delivery_failures(newsletter_issue_id, body)
+        .await;
+    let html_page = app.get_newsletter_issue_html(newsletter_issue_id).await;
+    assert!(html_page.contains("Status: in progress"));
+    assert!(html_page.contains("Pending: 1"));
+    assert!(html_page.contains(&format!(
+        r#"<a href="/admin/newsletters/{}/failures">0</a>"#,
+        newsletter_issue_id
+    )));
+
+    // Act 2 -- the queued delivery completes the issue
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    let html_page = app.get_newsletter_issue_html(newsletter_issue_id).await;
+    assert!(html_page.contains("Status: completed"));
+    assert!(html_page.contains("Sent: 1"));
+}
+
+#[tokio::test]
+async fn delivery_progress_is_tracked_per_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let rejected_email = sqlx::query!("SELECT email FROM subscriptions LIMIT 1")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::rejecting(&rejected_email))
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content":"<p>Newsletter body as HTML</P>",
+        "text_content":"Newsletter body as plain text",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_body_request).await;
+    let newsletter_issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id;
+
+    // Act 1 -- nothing has been sent yet
+    let html_page = app.get_newsletter_issue_html(newsletter_issue_id).await;
+    assert!(html_page.contains("Status: in progress"));
+    assert!(html_page.contains("Pending: 2"));
+
+    // Act 2 -- one delivery succeeds, the other one runs out of attempts
+    app.dispatch_all_pending_emails().await;
+    let html_page = app.get_newsletter_issue_html(newsletter_issue_id).await;
+    assert!(html_page.contains("Status: completed"));
+    assert!(html_page.contains("Pending: 0"));
+    assert!(html_page.contains("Sent: 1"));
+    assert!(html_page.contains(&format!(
+        r#"<a href="/admin/newsletters/{}/failures">1</a>"#,
+        newsletter_issue_id
+    )));
+
+    // Act 3 -- the issue shows up in the history listing
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains(&format!(
+        r#"<a href="/admin/newsletters/{}">Newsletter Title</a>"#,
+        newsletter_issue_id
+    )));
+}
+
+#[tokio::test]
+async fn only_the_rejected_recipients_of_a_batch_are_retried() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let rejected_email = sqlx::query!("SELECT email FROM subscriptions LIMIT 1")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email;
+
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::rejecting(&rejected_email))
+        .expect(app.worker_settings.max_attempts as u64)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content":"<p>Newsletter body as HTML</P>",
+        "text_content":"Newsletter body as plain text",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+
+    // Act
+    app.post_newsletters(&newsletter_body_request).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let batches: Vec<Vec<serde_json::Value>> = app
         .email_server
         .received_requests()
         .await
         .unwrap()
-        .pop()
-        .unwrap();
-
-    app.get_confirmation_links(&email_request)
+        .iter()
+        .filter(|r| r.url.path() == "/email/batch")
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect();
+    // Both subscribers go out in a single batch, only the rejected one is sent again
+    assert_eq!(batches[0].len(), 2);
+    for batch in &batches[1..] {
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0]["To"], rejected_email.as_str());
+    }
+    let failure = sqlx::query!("SELECT subscriber_email, error FROM issue_delivery_failures")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the failed delivery.");
+    assert_eq!(failure.subscriber_email, rejected_email);
+    assert!(failure.error.contains("406"));
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_link = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_link.html)
+#[tokio::test]
+async fn a_queued_delivery_without_a_subscriber_does_not_block_the_batch() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content":"<p>Newsletter body as HTML</P>",
+        "text_content":"Newsletter body as plain text",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_body_request).await;
+    // The queued address no longer matches any subscriber
+    let orphaned_email = sqlx::query!(
+        "UPDATE issue_delivery_queue SET subscriber_email = 'gone@example.com' \
+        WHERE subscriber_email = (SELECT subscriber_email FROM issue_delivery_queue LIMIT 1) \
+        RETURNING subscriber_email"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .subscriber_email;
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let batch: Vec<serde_json::Value> = app
+        .email_server
+        .received_requests()
         .await
         .unwrap()
-        .error_for_status()
+        .iter()
+        .find(|r| r.url.path() == "/email/batch")
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
         .unwrap();
+    assert_eq!(batch.len(), 1);
+    assert_ne!(batch[0]["To"], orphaned_email.as_str());
+    // The orphaned delivery is dropped, it is neither queued nor failed
+    let n_failures = sqlx::query!("SELECT COUNT(*) as \"count!\" FROM issue_delivery_failures")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_failures, 0);
+    let issue = sqlx::query!(
+        "SELECT n_recipients, n_delivered, n_failed, delivery_completed_at FROM newsletter_issues"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.n_recipients, 1);
+    assert_eq!(issue.n_delivered, 1);
+    assert_eq!(issue.n_failed, 0);
+    assert!(issue.delivery_completed_at.is_some());
 }
-// short hand for a commaon mocking setup
-fn when_sending_an_email() -> MockBuilder {
-    Mock::given(path("/email")).and(method("POST"))
+
+#[tokio::test]
+async fn the_worker_pool_drains_the_queue_and_stops_on_shutdown() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    create_confirmed_subscriber(&app).await;
+
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content":"<p>Newsletter body as HTML</P>",
+        "text_content":"Newsletter body as plain text",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_body_request).await;
+
+    // Act 1 -- let the worker pool pick up the deliveries
+    let (shutdown, worker) = app.spawn_worker();
+    let n_queued = || async {
+        sqlx::query!("SELECT COUNT(*) as \"count!\" FROM issue_delivery_queue")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .count
+    };
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while n_queued().await > 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("The worker pool did not drain the queue.");
+
+    // Act 2 -- ask the workers to stop
+    shutdown.cancel();
+    let outcome = tokio::time::timeout(Duration::from_secs(5), worker)
+        .await
+        .expect("The worker pool did not shut down.")
+        .unwrap();
+
+    // Assert
+    assert!(outcome.is_ok());
+    let n_delivered = sqlx::query!("SELECT n_delivered FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n_delivered;
+    assert_eq!(n_delivered, 2);
 }
@@ -0,0 +1,222 @@
+use crate::helper::{
+    assert_is_redirect_to, create_confirmed_subscriber, spawn_app, when_sending_a_newsletter,
+    when_sending_an_email, PostmarkBatchResponder, TestApp,
+};
+use uuid::Uuid;
+use wiremock::matchers::body_partial_json;
+use wiremock::ResponseTemplate;
+
+fn draft_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Draft Title",
+        "html_content": "<p>Draft body as HTML</p>",
+        "text_content": "Draft body as plain text",
+    })
+}
+
+async fn save_draft(app: &TestApp) -> Uuid {
+    let response = app.post_save_draft(&draft_body()).await;
+    assert_eq!(response.status().as_u16(), 303);
+    let location = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    location
+        .strip_prefix("/admin/newsletters/drafts/")
+        .expect("Not redirected to the draft page")
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn user_must_be_logged_in_to_see_drafts() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_draft(Uuid::new_v4()).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn drafts_are_listed_but_not_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let draft_id = save_draft(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let html_page = app.get_draft_html(draft_id).await;
+    assert!(html_page.contains("<p><i>The draft has been saved.</i></p>"));
+    let html_page = app.get_drafts_html().await;
+    assert!(html_page.contains(&format!(
+        r#"<a href="/admin/newsletters/drafts/{}">Draft Title</a>"#,
+        draft_id
+    )));
+    let html_page = app.get_newsletters_html().await;
+    assert!(!html_page.contains("Draft Title"));
+}
+
+#[tokio::test]
+async fn drafts_can_be_edited_and_previewed() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let draft_id = save_draft(&app).await;
+
+    // Act
+    let response = app
+        .post_update_draft(
+            draft_id,
+            &serde_json::json!({
+                "title": "Updated Title",
+                "html_content": "<p>Updated body</p>",
+                "text_content": "Updated plain text",
+            }),
+        )
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{}", draft_id),
+    );
+
+    // Assert
+    let html_page = app.get_draft_preview_html(draft_id).await;
+    assert!(html_page.contains("<h1>Updated Title</h1>"));
+    assert!(html_page.contains(r#"srcdoc="&lt;p&gt;Updated body&lt;/p&gt;""#));
+    assert!(html_page.contains("<pre>Updated plain text</pre>"));
+}
+
+#[tokio::test]
+async fn markdown_drafts_are_edited_in_markdown() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_save_draft(&serde_json::json!({
+            "title": "Draft Title",
+            "format": "markdown",
+            "markdown_content": "Some **bold** <news>",
+        }))
+        .await;
+    let draft_id: Uuid = response.headers()["Location"]
+        .to_str()
+        .unwrap()
+        .trim_start_matches("/admin/newsletters/drafts/")
+        .parse()
+        .unwrap();
+
+    // Assert
+    let html_page = app.get_draft_html(draft_id).await;
+    assert!(html_page.contains(r#"value="markdown" checked"#));
+    assert!(html_page.contains(">Some **bold** &lt;news&gt;</textarea>"));
+    let html_page = app.get_draft_preview_html(draft_id).await;
+    assert!(html_page.contains("&lt;strong&gt;bold&lt;/strong&gt;"));
+}
+
+#[tokio::test]
+async fn a_test_send_is_only_delivered_to_the_admin() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let draft_id = save_draft(&app).await;
+    let response = app
+        .post_change_email(&serde_json::json!({"email": "admin@example.com"}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/email");
+
+    when_sending_an_email()
+        .and(body_partial_json(serde_json::json!({
+            "To": "admin@example.com",
+            "Subject": "[TEST] Draft Title"
+        })))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_send_draft_test(draft_id).await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/drafts/{}", draft_id),
+    );
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let html_page = app.get_draft_html(draft_id).await;
+    assert!(html_page.contains("<p><i>A test email has been sent to admin@example.com.</i></p>"));
+}
+
+#[tokio::test]
+async fn a_test_send_requires_an_email_address() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let draft_id = save_draft(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_send_draft_test(draft_id).await;
+
+    // Assert
+    let html_page = app.get_draft_html(draft_id).await;
+    assert!(html_page.contains("to receive test sends."));
+}
+
+#[tokio::test]
+async fn publishing_a_draft_delivers_it_once() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let draft_id = save_draft(&app).await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let mut publish_body = draft_body();
+    publish_body["draft_id"] = draft_id.to_string().into();
+    publish_body["idempotency_key"] = Uuid::new_v4().to_string().into();
+
+    // Act -- submitting the publish form twice only publishes the draft once
+    let response = app.post_newsletters(&publish_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let response = app.post_newsletters(&publish_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let html_page = app.get_drafts_html().await;
+    assert!(!html_page.contains("Draft Title"));
+    let html_page = app.get_newsletter_issue_html(draft_id).await;
+    assert!(html_page.contains("Status: completed"));
+    let response = app.get_draft(draft_id).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -0,0 +1,293 @@
+use crate::helper::{
+    assert_is_redirect_to, create_confirmed_subscriber, spawn_app, when_sending_a_newsletter,
+    PostmarkBatchResponder, TestApp,
+};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+fn in_one_hour() -> String {
+    (Utc::now() + Duration::hours(1))
+        .format("%Y-%m-%dT%H:%M")
+        .to_string()
+}
+
+async fn schedule_newsletter(app: &TestApp, scheduled_for: &str) -> Uuid {
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content":"<p>Newsletter body as HTML</P>",
+        "text_content":"Newsletter body as plain text",
+        "scheduled_for": scheduled_for,
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    let response = app.post_newsletters(&newsletter_body_request).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+// `published_at` is when the issue was submitted, scheduling it does not move it to the future
+async fn assert_published_before_scheduled(app: &TestApp, newsletter_issue_id: Uuid) {
+    let issue = sqlx::query!(
+        "SELECT published_at, scheduled_for FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    let published_at = issue.published_at.unwrap();
+    assert!(published_at <= Utc::now());
+    assert!(published_at < issue.scheduled_for.unwrap());
+}
+
+async fn n_queued(app: &TestApp) -> i64 {
+    sqlx::query!("SELECT COUNT(*) as \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_not_delivered_before_they_are_due() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_issue_id = schedule_newsletter(&app, &in_one_hour()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(n_queued(&app).await, 1);
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains("The newsletter issue has been scheduled for"));
+    let html_page = app.get_newsletter_issue_html(newsletter_issue_id).await;
+    assert!(html_page.contains("Status: scheduled"));
+    assert_published_before_scheduled(&app, newsletter_issue_id).await;
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_delivered_once_they_are_due() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_issue_id = schedule_newsletter(&app, &in_one_hour()).await;
+
+    // Act -- fast forward to the scheduled time
+    sqlx::query!(
+        "UPDATE newsletter_issues SET scheduled_for = now() WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(n_queued(&app).await, 0);
+    let html_page = app.get_newsletter_issue_html(newsletter_issue_id).await;
+    assert!(html_page.contains("Status: completed"));
+}
+
+#[tokio::test]
+async fn scheduling_an_issue_in_the_past_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content":"<p>Newsletter body as HTML</P>",
+        "text_content":"Newsletter body as plain text",
+        "scheduled_for": "2020-01-01T09:30",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+
+    // Act
+    let response = app.post_newsletters(&newsletter_body_request).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Assert
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains("<p><i>The scheduled time must be in the future.</i></p>"));
+    let n_issues = sqlx::query!("SELECT COUNT(*) as \"count!\" FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_rescheduled() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = schedule_newsletter(&app, &in_one_hour()).await;
+    let tomorrow = Utc::now() + Duration::days(1);
+
+    // Act
+    let response = app
+        .post_reschedule_newsletter_issue(
+            newsletter_issue_id,
+            &serde_json::json!({"scheduled_for": tomorrow.format("%Y-%m-%dT%H:%M").to_string()}),
+        )
+        .await;
+
+    // Assert
+    let issue_page = format!("/admin/newsletters/{}", newsletter_issue_id);
+    assert_is_redirect_to(&response, &issue_page);
+    let html_page = app.get_newsletter_issue_html(newsletter_issue_id).await;
+    assert!(html_page.contains("The newsletter issue has been rescheduled for"));
+    assert!(html_page.contains(&tomorrow.format("%Y-%m-%dT%H:%M").to_string()));
+    assert_published_before_scheduled(&app, newsletter_issue_id).await;
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_cancelled() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_issue_id = schedule_newsletter(&app, &in_one_hour()).await;
+
+    // Act
+    let response = app.post_cancel_newsletter_issue(newsletter_issue_id).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Assert
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains("<p><i>The scheduled newsletter issue has been cancelled.</i></p>"));
+    assert_eq!(n_queued(&app).await, 0);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn issues_that_already_went_out_can_not_be_cancelled() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = schedule_newsletter(&app, "").await;
+
+    // Act
+    let response = app.post_cancel_newsletter_issue(newsletter_issue_id).await;
+
+    // Assert
+    let issue_page = format!("/admin/newsletters/{}", newsletter_issue_id);
+    assert_is_redirect_to(&response, &issue_page);
+    let html_page = app.get_newsletter_issue_html(newsletter_issue_id).await;
+    assert!(html_page.contains("Only issues that have not gone out yet can be cancelled."));
+}
+
+#[tokio::test]
+async fn the_content_of_scheduled_issues_can_be_edited() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    let newsletter_issue_id = schedule_newsletter(&app, &in_one_hour()).await;
+    let html_page = app
+        .get_edit_newsletter_issue_html(newsletter_issue_id)
+        .await;
+    assert!(html_page.contains(r#"value="Newsletter Title""#));
+
+    // Act
+    let response = app
+        .post_edit_newsletter_issue(
+            newsletter_issue_id,
+            &serde_json::json!({
+                "title": "Edited Title",
+                "format": "markdown",
+                "markdown_content": "Edited *body*"
+            }),
+        )
+        .await;
+
+    // Assert
+    let issue_page = format!("/admin/newsletters/{}", newsletter_issue_id);
+    assert_is_redirect_to(&response, &issue_page);
+    let html_page = app.get_newsletter_issue_html(newsletter_issue_id).await;
+    assert!(html_page.contains("The newsletter issue has been updated."));
+    let issue = sqlx::query!(
+        "SELECT title, html_content, markdown_content FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.title, "Edited Title");
+    assert!(issue.html_content.contains("<em>body</em>"));
+    assert_eq!(issue.markdown_content.as_deref(), Some("Edited *body*"));
+    assert_eq!(n_queued(&app).await, 1);
+}
+
+#[tokio::test]
+async fn invalid_edits_of_scheduled_issues_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = schedule_newsletter(&app, &in_one_hour()).await;
+
+    // Act
+    let response = app
+        .post_edit_newsletter_issue(
+            newsletter_issue_id,
+            &serde_json::json!({"title": "", "html_content": "<p>Body</p>"}),
+        )
+        .await;
+
+    // Assert
+    let edit_page = format!("/admin/newsletters/{}/edit", newsletter_issue_id);
+    assert_is_redirect_to(&response, &edit_page);
+    let html_page = app
+        .get_edit_newsletter_issue_html(newsletter_issue_id)
+        .await;
+    assert!(html_page.contains("<p><i>Title cannot be empty.</i></p>"));
+    let title = sqlx::query!("SELECT title FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .title;
+    assert_eq!(title, "Newsletter Title");
+}
+
+#[tokio::test]
+async fn issues_that_already_went_out_can_not_be_edited() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let newsletter_issue_id = schedule_newsletter(&app, "").await;
+
+    // Act
+    let response = app
+        .post_edit_newsletter_issue(
+            newsletter_issue_id,
+            &serde_json::json!({"title": "Edited Title", "html_content": "<p>Body</p>"}),
+        )
+        .await;
+
+    // Assert
+    let issue_page = format!("/admin/newsletters/{}", newsletter_issue_id);
+    assert_is_redirect_to(&response, &issue_page);
+    let html_page = app.get_newsletter_issue_html(newsletter_issue_id).await;
+    assert!(html_page.contains("Only issues that have not gone out yet can be edited."));
+}
@@ -0,0 +1,290 @@
+use crate::helper::{
+    assert_is_redirect_to, create_confirmed_subscriber_with, spawn_app, when_sending_a_newsletter,
+    when_sending_an_email, PostmarkBatchResponder, TestApp, TestSubscriber,
+};
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+async fn create_list(app: &TestApp, name: &str) -> Uuid {
+    let response = app.post_lists(&serde_json::json!({ "name": name })).await;
+    assert_is_redirect_to(&response, "/admin/lists");
+    sqlx::query!("SELECT list_id FROM lists WHERE name = $1", name)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .list_id
+}
+
+// Checkboxes send one `list_id` pair per selected list
+fn subscription_body(email: &str, list_ids: &[Uuid]) -> String {
+    let mut body = format!("name=Ursula&email={}", email.replace('@', "%40"));
+    for list_id in list_ids {
+        body.push_str(&format!("&list_id={}", list_id));
+    }
+    body
+}
+
+async fn publish_newsletter(app: &TestApp, list_ids: &[Uuid]) -> reqwest::Response {
+    let mut body = format!(
+        "title=Title&html_content=%3Cp%3EBody%3C%2Fp%3E&text_content=Body&idempotency_key={}",
+        Uuid::new_v4()
+    );
+    for list_id in list_ids {
+        body.push_str(&format!("&list_id={}", list_id));
+    }
+    app.api_client
+        .post(format!("{}/admin/newsletters", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn recipients(app: &TestApp) -> Vec<String> {
+    let mut recipients = Vec::new();
+    for request in app.email_server.received_requests().await.unwrap() {
+        if request.url.path() != "/email/batch" {
+            continue;
+        }
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        for message in body.as_array().unwrap() {
+            recipients.push(message["To"].as_str().unwrap().to_owned());
+        }
+    }
+    recipients.sort();
+    recipients
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_lists() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_lists(&serde_json::json!({ "name": "Rust" })).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn lists_are_created_with_unique_names() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act 1 - Create a list
+    create_list(&app, "Rust <news>").await;
+
+    // Assert
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("The list Rust &lt;news&gt; has been created."));
+    assert!(html_page.contains("<td>Rust &lt;news&gt;</td>"));
+
+    // Act 2 - Create it again
+    app.post_lists(&serde_json::json!({ "name": " Rust <news> " }))
+        .await;
+
+    // Assert
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("There already is a list named Rust &lt;news&gt;."));
+
+    // Act 3 - Create a list without a name
+    app.post_lists(&serde_json::json!({ "name": "  " })).await;
+
+    // Assert
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("The list name cannot be empty."));
+}
+
+#[tokio::test]
+async fn subscribers_join_the_lists_they_select() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let rust = create_list(&app, "Rust").await;
+    create_list(&app, "Python").await;
+
+    // Act
+    let subscribe_form = app
+        .api_client
+        .get(format!("{}/subscriptions", app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    create_confirmed_subscriber_with(
+        &app,
+        TestSubscriber {
+            email: Some("ursula@example.com"),
+            list_ids: &[rust],
+            ..Default::default()
+        },
+    )
+    .await;
+
+    // Assert
+    assert!(subscribe_form.contains(&format!(
+        r#"<input type="checkbox" name="list_id" value="{}"> Rust"#,
+        rust
+    )));
+    let memberships = sqlx::query!("SELECT list_id FROM subscriptions_lists")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(memberships.len(), 1);
+    assert_eq!(memberships[0].list_id, rust);
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("<td>Rust</td>\n                <td>1</td>"));
+    assert!(html_page.contains("<td>Python</td>\n                <td>0</td>"));
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_subscriptions(subscription_body("ursula@example.com", &[Uuid::new_v4()]))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_optional(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_none());
+}
+
+#[tokio::test]
+async fn newsletters_sent_to_lists_only_reach_their_confirmed_members() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let rust = create_list(&app, "Rust").await;
+    let python = create_list(&app, "Python").await;
+    let go = create_list(&app, "Go").await;
+    create_confirmed_subscriber_with(
+        &app,
+        TestSubscriber {
+            email: Some("rust@example.com"),
+            list_ids: &[rust],
+            ..Default::default()
+        },
+    )
+    .await;
+    create_confirmed_subscriber_with(
+        &app,
+        TestSubscriber {
+            email: Some("both@example.com"),
+            list_ids: &[rust, python],
+            ..Default::default()
+        },
+    )
+    .await;
+    create_confirmed_subscriber_with(
+        &app,
+        TestSubscriber {
+            email: Some("go@example.com"),
+            list_ids: &[go],
+            ..Default::default()
+        },
+    )
+    .await;
+    create_confirmed_subscriber_with(
+        &app,
+        TestSubscriber {
+            email: Some("none@example.com"),
+            list_ids: &[],
+            ..Default::default()
+        },
+    )
+    .await;
+    let _mock_guard = when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(subscription_body("pending@example.com", &[rust]))
+        .await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = publish_newsletter(&app, &[rust, python]).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    assert_eq!(
+        recipients(&app).await,
+        vec!["both@example.com", "rust@example.com"]
+    );
+}
+
+#[tokio::test]
+async fn newsletters_sent_to_no_list_reach_every_confirmed_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let rust = create_list(&app, "Rust").await;
+    create_confirmed_subscriber_with(
+        &app,
+        TestSubscriber {
+            email: Some("rust@example.com"),
+            list_ids: &[rust],
+            ..Default::default()
+        },
+    )
+    .await;
+    create_confirmed_subscriber_with(
+        &app,
+        TestSubscriber {
+            email: Some("none@example.com"),
+            list_ids: &[],
+            ..Default::default()
+        },
+    )
+    .await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_newsletter(&app, &[]).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(
+        recipients(&app).await,
+        vec!["none@example.com", "rust@example.com"]
+    );
+}
+
+#[tokio::test]
+async fn newsletters_sent_to_a_deleted_list_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = publish_newsletter(&app, &[Uuid::new_v4()]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains("Some of the selected lists no longer exist."));
+    let n_issues = sqlx::query!("SELECT COUNT(*) as \"count!\" FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_issues, 0);
+}
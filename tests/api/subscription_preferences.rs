@@ -0,0 +1,385 @@
+use crate::helper::{
+    create_confirmed_subscriber_with, spawn_app, when_sending_a_newsletter, when_sending_an_email,
+    PostmarkBatchResponder, TestApp, TestSubscriber,
+};
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+// The first link following `label` in the plain text body of the last email sent
+async fn link_after(app: &TestApp, label: &str) -> reqwest::Url {
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    // Newsletters go through the batch endpoint, other emails are sent one at a time
+    let text_body = body
+        .get(0)
+        .unwrap_or(&body)
+        .get("TextBody")
+        .unwrap()
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let raw_link = text_body
+        .split(label)
+        .nth(1)
+        .expect("No link in the email")
+        .split_whitespace()
+        .next()
+        .unwrap();
+    let mut link = reqwest::Url::parse(raw_link).unwrap();
+    assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+// Subscribers reach their preferences from the footer of every issue
+async fn preferences_link(app: &TestApp) -> reqwest::Url {
+    app.test_user.login(app).await;
+    let _mock_guard = when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "text_content": "Newsletter body as plain text",
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+    link_after(app, "Manage your subscription: ").await
+}
+
+async fn post_preferences(app: &TestApp, link: &reqwest::Url, body: String) -> reqwest::Response {
+    let response = app
+        .api_client
+        .post(link.clone())
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    // Email change confirmations go through the outbox, send them as the worker would
+    app.dispatch_all_outbox_emails().await;
+    response
+}
+
+async fn get_preferences_html(app: &TestApp, link: &reqwest::Url) -> String {
+    app.api_client
+        .get(link.clone())
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap()
+}
+
+async fn saved_subscriber(app: &TestApp) -> (String, String) {
+    let saved = sqlx::query!("SELECT name, email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    (saved.name, saved.email)
+}
+
+#[tokio::test]
+async fn newsletter_emails_link_to_the_preferences_page() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with(
+        &app,
+        TestSubscriber {
+            name: Some("Ursula"),
+            email: Some("ursula@example.com"),
+            ..Default::default()
+        },
+    )
+    .await;
+    let link = preferences_link(&app).await;
+
+    // Act
+    let response = app.api_client.get(link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"name="name" value="Ursula""#));
+    assert!(html_page.contains(r#"name="email" value="ursula@example.com""#));
+}
+
+#[tokio::test]
+async fn a_tampered_preferences_link_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with(
+        &app,
+        TestSubscriber {
+            name: Some("Ursula"),
+            email: Some("ursula@example.com"),
+            ..Default::default()
+        },
+    )
+    .await;
+    let mut link = preferences_link(&app).await;
+    let token = link.query_pairs().next().unwrap().1.into_owned();
+    let (_, tag) = token.rsplit_once('.').unwrap();
+    link.query_pairs_mut()
+        .clear()
+        .append_pair("token", &format!("{}.{}", Uuid::new_v4(), tag));
+
+    // Act
+    let response = app.api_client.get(link.clone()).send().await.unwrap();
+    let post_response =
+        post_preferences(&app, &link, "name=Mallory&email=m%40example.com".into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert_eq!(post_response.status().as_u16(), 401);
+    assert_eq!(saved_subscriber(&app).await.0, "Ursula");
+}
+
+#[tokio::test]
+async fn subscribers_can_change_their_name_and_lists() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with(
+        &app,
+        TestSubscriber {
+            name: Some("Ursula"),
+            email: Some("ursula@example.com"),
+            ..Default::default()
+        },
+    )
+    .await;
+    let link = preferences_link(&app).await;
+    for name in ["Rust", "Python"] {
+        app.post_lists(&serde_json::json!({ "name": name })).await;
+    }
+    let rust = sqlx::query!("SELECT list_id FROM lists WHERE name = 'Rust'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .list_id;
+
+    // Act
+    let response = post_preferences(
+        &app,
+        &link,
+        format!("name=Ursula+K&email=ursula%40example.com&list_id={}", rust),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+    let html_page = get_preferences_html(&app, &link).await;
+    assert!(html_page.contains("Your preferences have been saved."));
+    assert!(html_page.contains(&format!(
+        r#"<input type="checkbox" name="list_id" value="{}" checked> Rust"#,
+        rust
+    )));
+    assert!(!html_page.contains("checked> Python"));
+    assert_eq!(
+        saved_subscriber(&app).await,
+        ("Ursula K".into(), "ursula@example.com".into())
+    );
+}
+
+#[tokio::test]
+async fn invalid_preferences_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with(
+        &app,
+        TestSubscriber {
+            name: Some("Ursula"),
+            email: Some("ursula@example.com"),
+            ..Default::default()
+        },
+    )
+    .await;
+    let link = preferences_link(&app).await;
+
+    for (body, error_message) in [
+        (
+            "name=%3Cscript%3E&email=ursula%40example.com",
+            "&lt;script&gt; is not a valid subscriber name.",
+        ),
+        (
+            "name=Ursula&email=not-an-email",
+            "not-an-email is not a valid subscriber email.",
+        ),
+    ] {
+        // Act
+        post_preferences(&app, &link, body.into()).await;
+
+        // Assert
+        let html_page = get_preferences_html(&app, &link).await;
+        assert!(html_page.contains(error_message));
+        assert_eq!(
+            saved_subscriber(&app).await,
+            ("Ursula".into(), "ursula@example.com".into())
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_new_email_address_is_only_used_once_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with(
+        &app,
+        TestSubscriber {
+            name: Some("Ursula"),
+            email: Some("ursula@example.com"),
+            ..Default::default()
+        },
+    )
+    .await;
+    let link = preferences_link(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act 1 - Ask for the change
+    post_preferences(
+        &app,
+        &link,
+        "name=Ursula&email=le.guin%40example.com".into(),
+    )
+    .await;
+
+    // Assert
+    let html_page = get_preferences_html(&app, &link).await;
+    assert!(html_page.contains("We sent a confirmation link to le.guin@example.com"));
+    assert_eq!(saved_subscriber(&app).await.1, "ursula@example.com");
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "le.guin@example.com");
+
+    // Act 2 - Follow the confirmation link
+    let confirmation_link = link_after(&app, "Visit ").await;
+    let response = app
+        .api_client
+        .get(confirmation_link.clone())
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(saved_subscriber(&app).await.1, "le.guin@example.com");
+
+    // Act 3 - Follow it again
+    let response = app.api_client.get(confirmation_link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn an_email_address_that_is_already_subscribed_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with(
+        &app,
+        TestSubscriber {
+            name: Some("Ursula"),
+            email: Some("ursula@example.com"),
+            ..Default::default()
+        },
+    )
+    .await;
+    let link = preferences_link(&app).await;
+    create_confirmed_subscriber_with(
+        &app,
+        TestSubscriber {
+            name: Some("Ursula"),
+            email: Some("taken@example.com"),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    // Act
+    post_preferences(&app, &link, "name=Ursula&email=taken%40example.com".into()).await;
+
+    // Assert
+    let html_page = get_preferences_html(&app, &link).await;
+    assert!(html_page.contains("taken@example.com is already subscribed."));
+}
+
+#[tokio::test]
+async fn issues_queued_before_an_email_change_go_to_the_new_address() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with(
+        &app,
+        TestSubscriber {
+            name: Some("Ursula"),
+            email: Some("ursula@example.com"),
+            ..Default::default()
+        },
+    )
+    .await;
+    let link = preferences_link(&app).await;
+    when_sending_an_email()
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    post_preferences(
+        &app,
+        &link,
+        "name=Ursula&email=le.guin%40example.com".into(),
+    )
+    .await;
+    let confirmation_link = link_after(&app, "Visit ").await;
+    app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "text_content": "Newsletter body as plain text",
+        "idempotency_key": Uuid::new_v4().to_string()
+    }))
+    .await;
+
+    // Act
+    app.api_client
+        .get(confirmation_link)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body[0]["To"], "le.guin@example.com");
+}
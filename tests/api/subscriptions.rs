@@ -1,4 +1,4 @@
-use crate::helper::spawn_app;
+use crate::helper::{spawn_app, TestApp};
 use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
@@ -159,3 +159,237 @@ async fn subscribe_returns_400_when_fields_are_present_but_invalid() {
         );
     }
 }
+
+async fn saved_subscribers(app: &TestApp) -> Vec<(String, String)> {
+    sqlx::query!("SELECT name, status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|s| (s.name, s.status))
+        .collect()
+}
+
+async fn confirm_last_email(app: &TestApp) {
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn subscribing_again_while_pending_sends_a_new_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=John73&email=john_r77%40gmail.com".into())
+        .await;
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '1 hour'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app
+        .post_subscriptions("name=John&email=john_r77%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    confirm_last_email(&app).await;
+    assert_eq!(
+        saved_subscribers(&app).await,
+        vec![("John".into(), "confirmed".into())]
+    );
+}
+
+#[tokio::test]
+async fn subscribing_again_while_pending_is_rate_limited() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=John73&email=john_r77%40gmail.com".into())
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=John73&email=john_r77%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribing_again_when_confirmed_changes_nothing() {
+    // Arrange
+    let app = spawn_app().await;
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions("name=John73&email=john_r77%40gmail.com".into())
+        .await;
+    confirm_last_email(&app).await;
+    drop(mock_guard);
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=Mallory&email=john_r77%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        saved_subscribers(&app).await,
+        vec![("John73".into(), "confirmed".into())]
+    );
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_opt_in_again_by_confirming_their_address() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=John73&email=john_r77%40gmail.com".into())
+        .await;
+    confirm_last_email(&app).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act 1 - Subscribe again
+    let response = app
+        .post_subscriptions("name=John73&email=john_r77%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        saved_subscribers(&app).await,
+        vec![("John73".into(), "pending_confirmation".into())]
+    );
+
+    // Act 2 - Confirm
+    confirm_last_email(&app).await;
+
+    // Assert
+    assert_eq!(
+        saved_subscribers(&app).await,
+        vec![("John73".into(), "confirmed".into())]
+    );
+}
+
+#[tokio::test]
+async fn subscribe_succeeds_while_the_email_provider_is_down() {
+    // Arrange
+    let mut app = spawn_app().await;
+    // Leave the failed email waiting for its retry
+    app.worker_settings.initial_backoff_milliseconds = 60_000;
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    // Act 1 - Subscribe during the outage
+    let response = app
+        .post_subscriptions("name=John73&email=john_r77%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let outbox = sqlx::query!("SELECT n_attempts, last_error FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(outbox.n_attempts, 1);
+    assert!(outbox.last_error.is_some());
+    drop(mock_guard);
+
+    // Act 2 - The provider is back when the email is retried
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    sqlx::query!("UPDATE email_outbox SET next_attempt_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_outbox_emails().await;
+
+    // Assert
+    confirm_last_email(&app).await;
+    let n_queued = sqlx::query!("SELECT count(*) as \"n!\" FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_queued, 0);
+    assert_eq!(
+        saved_subscribers(&app).await,
+        vec![("John73".into(), "confirmed".into())]
+    );
+}
+
+#[tokio::test]
+async fn confirmation_emails_are_abandoned_after_the_maximum_number_of_attempts() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(u64::try_from(app.worker_settings.max_attempts).unwrap())
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=John73&email=john_r77%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let outbox = sqlx::query!("SELECT n_attempts, failed_at FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(outbox.n_attempts, app.worker_settings.max_attempts);
+    assert!(outbox.failed_at.is_some());
+}
@@ -1,5 +1,4 @@
-use crate::helper::spawn_app;
-use reqwest;
+use crate::helper::{create_confirmed_subscriber_with, spawn_app, TestApp, TestSubscriber};
 use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
@@ -76,3 +75,272 @@ async fn confirmation_without_token_are_rejected_with_a_400() {
     // Assert
     assert_eq!(response.status().as_u16(), 400);
 }
+
+// Moves every confirmation token back in time, as if the emails had been sent a while ago
+async fn age_confirmation_tokens(app: &TestApp, hours: i32) {
+    sqlx::query!(
+        "UPDATE subscription_tokens SET created_at = created_at - make_interval(hours => $1)",
+        hours
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn saved_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[tokio::test]
+async fn following_a_confirmation_link_again_shows_it_is_already_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=John73&email=john_r77%40gmail.com".into())
+        .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // Act 1 - Confirm
+    let response = reqwest::get(confirmation_links.html.clone()).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("<h1>Subscription confirmed</h1>"));
+
+    // Act 2 - Follow the link again
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Your subscription was already confirmed"));
+}
+
+#[tokio::test]
+async fn an_unknown_confirmation_link_is_rejected_with_a_page() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=unknown",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(response
+        .headers()
+        .get("Content-Type")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("This confirmation link is not valid."));
+}
+
+#[tokio::test]
+async fn an_expired_confirmation_link_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=John73&email=john_r77%40gmail.com".into())
+        .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    age_confirmation_tokens(&app, 25).await;
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This confirmation link has expired"));
+    assert!(
+        html_page.contains(r#"<form action="/subscriptions/resend-confirmation" method="post">"#)
+    );
+    assert_eq!(saved_status(&app).await, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn pending_subscribers_can_ask_for_a_new_confirmation_link() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=John73&email=john_r77%40gmail.com".into())
+        .await;
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_links = app.get_confirmation_links(&email_requests[0]);
+    age_confirmation_tokens(&app, 25).await;
+
+    // Act
+    let response = app
+        .post_resend_confirmation("email=john_r77%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let new_links = app.get_confirmation_links(&email_requests[1]);
+    assert_ne!(first_links.html, new_links.html);
+    reqwest::get(new_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    assert_eq!(saved_status(&app).await, "confirmed");
+}
+
+#[tokio::test]
+async fn confirming_a_subscriber_consumes_every_link_sent_to_them() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=John73&email=john_r77%40gmail.com".into())
+        .await;
+    // Old enough to be resent, still young enough to be valid
+    age_confirmation_tokens(&app, 1).await;
+    app.post_resend_confirmation("email=john_r77%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    let first_links = app.get_confirmation_links(&email_requests[0]);
+    let new_links = app.get_confirmation_links(&email_requests[1]);
+    reqwest::get(new_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    // Once unsubscribed, an unused link must not sign the subscriber up again
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(first_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("This confirmation link has already been used."));
+    assert_eq!(saved_status(&app).await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn resending_a_confirmation_email_is_rate_limited() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=John73&email=john_r77%40gmail.com".into())
+        .await;
+
+    // Act
+    let response = app
+        .post_resend_confirmation("email=john_r77%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("a new confirmation link is on its way"));
+    let n_tokens = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tokens, 1);
+}
+
+#[tokio::test]
+async fn no_confirmation_email_is_resent_to_unknown_or_confirmed_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with(
+        &app,
+        TestSubscriber {
+            email: Some("john_r77@gmail.com"),
+            ..Default::default()
+        },
+    )
+    .await;
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    age_confirmation_tokens(&app, 1).await;
+
+    for email in ["unknown%40example.com", "john_r77%40gmail.com"] {
+        // Act
+        let response = app
+            .post_resend_confirmation(format!("email={}", email))
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+    }
+}
+
+#[tokio::test]
+async fn resending_a_confirmation_email_rejects_invalid_addresses() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_resend_confirmation("email=not-an-email".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
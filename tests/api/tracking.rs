@@ -0,0 +1,152 @@
+use crate::helper::{
+    create_confirmed_subscriber, spawn_app, when_sending_a_newsletter, PostmarkBatchResponder,
+    TestApp,
+};
+
+async fn publish_newsletter(app: &TestApp) {
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content": r#"<p>Read <a href="https://example.com/article">the article</a></p>"#,
+        "text_content": "Newsletter body as plain text",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_body_request).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+async fn sent_html_body(app: &TestApp) -> String {
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    body[0]["HtmlBody"].as_str().unwrap().to_owned()
+}
+
+// The first `attribute="..."` value in `html` that contains `needle`, pointed at the test server
+fn tracking_link(app: &TestApp, html: &str, attribute: &str, needle: &str) -> reqwest::Url {
+    let prefix = format!(r#"{}=""#, attribute);
+    let raw_link = html
+        .split(&prefix)
+        .skip(1)
+        .filter_map(|rest| rest.split('"').next())
+        .find(|link| link.contains(needle))
+        .expect("No tracking link");
+    let mut link = reqwest::Url::parse(raw_link).unwrap();
+    assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+#[tokio::test]
+async fn opens_and_clicks_are_recorded_and_shown_on_the_dashboard() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+    let html_body = sent_html_body(&app).await;
+    assert!(!html_body.contains(r#"href="https://example.com/article""#));
+    let click_link = tracking_link(&app, &html_body, "href", "/t/");
+    let pixel_link = tracking_link(&app, &html_body, "src", "/open.gif");
+
+    // Act 1 -- the subscriber opens the issue twice and clicks the link
+    for _ in 0..2 {
+        let response = app.api_client.get(pixel_link.clone()).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["Content-Type"], "image/gif");
+    }
+    let response = app.api_client.get(click_link).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(
+        response.headers()["Location"],
+        "https://example.com/article"
+    );
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(
+        r#"<td>1</td>
+                <td>1</td>
+                <td>1</td>
+                <td>1</td>"#
+    ));
+    let n_events = sqlx::query!(r#"SELECT count(*) as "n!" FROM newsletter_events"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .n;
+    assert_eq!(n_events, 3);
+}
+
+#[tokio::test]
+async fn the_unsubscribe_link_is_not_tracked() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_newsletter(&app).await;
+
+    // Assert
+    let html_body = sent_html_body(&app).await;
+    assert!(html_body.contains(r#"/subscriptions/unsubscribe?token="#));
+    assert_eq!(html_body.matches("/t/").count(), 2);
+}
+
+#[tokio::test]
+async fn tracking_can_be_turned_off() {
+    // Arrange
+    let mut app = spawn_app().await;
+    app.worker_settings.track_opens = false;
+    app.worker_settings.track_clicks = false;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_newsletter(&app).await;
+
+    // Assert
+    let html_body = sent_html_body(&app).await;
+    assert!(html_body.contains(r#"href="https://example.com/article""#));
+    assert!(!html_body.contains("/t/"));
+}
+
+#[tokio::test]
+async fn invalid_tracking_tokens_are_rejected_with_a_404() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = format!("{}.{}", uuid::Uuid::new_v4(), "00".repeat(32));
+
+    for path in [format!("/t/{}", token), format!("/t/{}/open.gif", token)] {
+        // Act
+        let response = app
+            .api_client
+            .get(format!("{}{}", app.address, path))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 404);
+    }
+}
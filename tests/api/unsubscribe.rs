@@ -0,0 +1,220 @@
+use crate::helper::{
+    create_confirmed_subscriber, create_confirmed_subscriber_with, spawn_app,
+    when_sending_a_newsletter, PostmarkBatchResponder, TestApp, TestSubscriber,
+};
+
+async fn publish_newsletter(app: &TestApp) {
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content":"<p>Newsletter body as HTML</P>",
+        "text_content":"Newsletter body as plain text",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_body_request).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+fn list_unsubscribe_link(app: &TestApp, email_request: &wiremock::Request) -> reqwest::Url {
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let header = body[0]["Headers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|h| h["Name"] == "List-Unsubscribe")
+        .expect("No List-Unsubscribe header");
+    let raw_link = header["Value"]
+        .as_str()
+        .unwrap()
+        .trim_start_matches('<')
+        .trim_end_matches('>');
+    let mut link = reqwest::Url::parse(raw_link).unwrap();
+    assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+    link.set_port(Some(app.port)).unwrap();
+    link
+}
+
+#[tokio::test]
+async fn newsletter_emails_carry_a_personalized_unsubscribe_link() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_newsletter(&app).await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let body = &body[0];
+    let headers = body["Headers"].as_array().unwrap();
+    assert!(headers.iter().any(
+        |h| h["Name"] == "List-Unsubscribe-Post" && h["Value"] == "List-Unsubscribe=One-Click"
+    ));
+    let link = list_unsubscribe_link(&app, &email_request);
+    assert_eq!(link.path(), "/subscriptions/unsubscribe");
+    let mut raw_link = link.clone();
+    raw_link.set_port(None).unwrap();
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains(raw_link.as_str()));
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains(raw_link.as_str()));
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_stops_further_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let link = list_unsubscribe_link(&app, &email_request);
+
+    // Act 1 -- the mail client performs a one-click unsubscribe
+    let response = reqwest::Client::new()
+        .post(link)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Assert
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+
+    // Act 2 -- a new issue is not sent to the unsubscribed subscriber
+    publish_newsletter(&app).await;
+    // Mock verifies on Drop that only the first issue was sent
+}
+
+#[tokio::test]
+async fn issues_queued_before_unsubscribing_are_not_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let subscriber_id = create_confirmed_subscriber_with(&app, TestSubscriber::default()).await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let newsletter_body_request = serde_json::json!({
+        "title": "Newsletter Title",
+        "html_content":"<p>Newsletter body as HTML</P>",
+        "text_content":"Newsletter body as plain text",
+        "idempotency_key": uuid::Uuid::new_v4().to_string()
+    });
+    app.post_newsletters(&newsletter_body_request).await;
+    let mut unsubscribe_url = app.link_signer.unsubscribe_url(subscriber_id);
+    unsubscribe_url.set_port(Some(app.port)).unwrap();
+
+    // Act
+    app.api_client
+        .post(unsubscribe_url)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let n_queued = sqlx::query!("SELECT COUNT(*) as \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_queued, 0);
+    let issue =
+        sqlx::query!("SELECT n_recipients, n_failed, delivery_completed_at FROM newsletter_issues")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(issue.n_recipients, 0);
+    assert_eq!(issue.n_failed, 0);
+    assert!(issue.delivery_completed_at.is_some());
+    // Mock verifies on Drop that nothing was sent
+}
+
+#[tokio::test]
+async fn the_unsubscribe_link_asks_for_confirmation_before_unsubscribing() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_confirmed_subscriber(&app).await;
+    when_sending_a_newsletter()
+        .respond_with(PostmarkBatchResponder::default())
+        .mount(&app.email_server)
+        .await;
+    publish_newsletter(&app).await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let link = list_unsubscribe_link(&app, &email_request);
+
+    // Act
+    let response = reqwest::get(link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("<form"));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribe_with_an_invalid_token_is_rejected_with_a_401() {
+    // Arrange
+    let app = spawn_app().await;
+    let token = format!("{}.{}", uuid::Uuid::new_v4(), "00".repeat(32));
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions/unsubscribe", app.address))
+        .query(&[("token", token)])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}